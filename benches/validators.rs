@@ -0,0 +1,198 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use dpos::acc_impl;
+use dpos::config::ValidatorConfig;
+use dpos::new_impl;
+use dpos::new_impl::Settlement;
+use dpos::old_impl;
+use dpos::Address;
+use dpos::Amount;
+use std::hint::black_box;
+
+const BALANCE: Amount = 1_000_000_000;
+const VOTE: Amount = 1000;
+const REWARD: Amount = 1_000_000;
+
+// Addresses far enough apart for the support keys of the index-based validator not to collide
+fn address(id: usize) -> Address {
+    (id as Address + 1) << 64
+}
+
+fn old_validator(delegators: usize) -> old_impl::Validator {
+    let mut validator = old_impl::Validator::new(&ValidatorConfig::default()).unwrap();
+    for id in 0..delegators {
+        let mut user = old_impl::User {
+            address: address(id),
+            balance: BALANCE,
+        };
+        validator.vote(&mut user, VOTE);
+    }
+    validator
+}
+
+fn new_validator(delegators: usize) -> (new_impl::Validator, Vec<new_impl::User>) {
+    let mut validator = new_impl::Validator::new(&ValidatorConfig::default()).unwrap();
+    let mut users = vec![];
+    for id in 0..delegators {
+        let mut user = new_impl::User {
+            address: address(id),
+            balance: BALANCE,
+        };
+        validator.vote(&mut user, VOTE);
+        users.push(user);
+    }
+    (validator, users)
+}
+
+fn acc_validator(delegators: usize) -> (acc_impl::Validator, Vec<new_impl::User>) {
+    let mut validator = acc_impl::Validator::new(&ValidatorConfig::default()).unwrap();
+    let mut users = vec![];
+    for id in 0..delegators {
+        let mut user = new_impl::User {
+            address: address(id),
+            balance: BALANCE,
+        };
+        validator.vote(&mut user, VOTE);
+        users.push(user);
+    }
+    (validator, users)
+}
+
+// A new delegator votes while 10k others already have
+fn vote(c: &mut Criterion) {
+    const DELEGATORS: usize = 10_000;
+    let mut group = c.benchmark_group("vote with 10k delegators");
+
+    let mut validator = old_validator(DELEGATORS);
+    let mut id = DELEGATORS;
+    group.bench_function("old_impl", |b| {
+        b.iter(|| {
+            let mut user = old_impl::User {
+                address: address(id),
+                balance: BALANCE,
+            };
+            id += 1;
+            black_box(validator.vote(&mut user, VOTE))
+        })
+    });
+
+    let (mut validator, _) = new_validator(DELEGATORS);
+    let mut id = DELEGATORS;
+    group.bench_function("new_impl", |b| {
+        b.iter(|| {
+            let mut user = new_impl::User {
+                address: address(id),
+                balance: BALANCE,
+            };
+            id += 1;
+            validator.vote(&mut user, VOTE)
+        })
+    });
+
+    let (mut validator, _) = acc_validator(DELEGATORS);
+    let mut id = DELEGATORS;
+    group.bench_function("acc_impl", |b| {
+        b.iter(|| {
+            let mut user = new_impl::User {
+                address: address(id),
+                balance: BALANCE,
+            };
+            id += 1;
+            validator.vote(&mut user, VOTE)
+        })
+    });
+
+    group.finish();
+}
+
+// Withdrawal of a support deposited the given number of indexes ago
+fn old_withdraw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("old_impl withdraw by index gap");
+
+    for gap in [10, 100, 1000] {
+        let config = ValidatorConfig::builder()
+            .max_index_delta(gap)
+            .build()
+            .unwrap();
+        let mut validator = old_impl::Validator::new(&config).unwrap();
+        let mut user = old_impl::User {
+            address: address(0),
+            balance: BALANCE,
+        };
+        let (from_index, _) = validator.vote(&mut user, VOTE);
+        for reward_id in 0..gap {
+            validator.append_reward(reward_id as u64, REWARD);
+        }
+
+        group.bench_with_input(BenchmarkId::from_parameter(gap), &gap, |b, _| {
+            b.iter_batched(
+                || {
+                    let user = old_impl::User {
+                        address: address(0),
+                        balance: user.balance,
+                    };
+                    (validator.clone(), user)
+                },
+                |(mut validator, mut user)| {
+                    let version = validator.version;
+                    black_box(
+                        validator
+                            .try_withdraw_with_rewards(&mut user, from_index, VOTE, version, None)
+                            .unwrap(),
+                    )
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+// Settling a reward for every delegator
+fn distribute(c: &mut Criterion) {
+    let mut group = c.benchmark_group("distribute rewards");
+    group.sample_size(10);
+
+    for delegators in [1_000, 10_000] {
+        group.bench_with_input(
+            BenchmarkId::new("new_impl", delegators),
+            &delegators,
+            |b, &delegators| {
+                b.iter_batched(
+                    || {
+                        let (mut validator, _) = new_validator(delegators);
+                        validator.append_reward(0, REWARD);
+                        validator
+                    },
+                    |mut validator| black_box(validator.force_settle_all(Settlement::Accrue)),
+                    BatchSize::PerIteration,
+                )
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("acc_impl", delegators),
+            &delegators,
+            |b, &delegators| {
+                b.iter_batched(
+                    || {
+                        let (mut validator, users) = acc_validator(delegators);
+                        validator.append_reward(0, REWARD);
+                        (validator, users)
+                    },
+                    |(mut validator, mut users)| {
+                        for user in users.iter_mut() {
+                            validator.send_rewards(user);
+                        }
+                    },
+                    BatchSize::PerIteration,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, vote, old_withdraw, distribute);
+criterion_main!(benches);
@@ -0,0 +1,524 @@
+use super::audit::{AuditLog, AuditOp};
+use super::config::{ConfigError, ConfigViolation, ValidatorConfig};
+use super::new_impl::User;
+use super::traits::{DposError, Staking};
+use super::Address;
+use super::Amount;
+use super::RewardIndex;
+use super::RoundingPolicy;
+use super::ValidatorState;
+use super::SHARE;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Fixed-point base of the accumulated reward per share
+const ACC_PRECISION: u128 = 1_000_000_000_000;
+
+#[derive(Serialize, Deserialize)]
+pub struct Stake {
+    // Stake amount
+    pub amount: Amount,
+    // Part of the accumulated reward for the amount that has already been settled
+    pub reward_debt: Amount,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Validator {
+    // Users stakes by their addresses
+    pub stakes: HashMap<Address, Stake>,
+    // Delegated balance on that account
+    pub total_delegated: Amount,
+    // Total balance on that account (delegated + rewarded)
+    pub total_balance: Amount,
+    // Users reward per delegated token accumulated over all rewards (ACC_PRECISION is 1.0)
+    pub acc_reward_per_share: Amount,
+    // Minimal amount of a single vote (DEFAULT_MIN_VOTE by default)
+    pub min_vote: Amount,
+    // Maximum delegated balance on that account, unlimited if none
+    pub max_total_delegated: Option<Amount>,
+    // Maximum number of delegators, unlimited if none
+    pub max_delegators: Option<usize>,
+    // Indicates that votes and rewards are not accepted, withdrawals are still available
+    pub paused: bool,
+    // Minimal reward to send to a user, smaller rewards are accrued until they reach it
+    pub min_payout: Amount,
+    // Accrued rewards that have not been sent to users yet, they are still held on the account
+    pub accrued: HashMap<Address, Amount>,
+    // Lifecycle state, votes and rewards are accepted only by active validators
+    pub state: ValidatorState,
+    // External id of the last appended reward (block number or event id)
+    pub last_reward_id: Option<u64>,
+    // Number of appended rewards
+    pub rewards_count: RewardIndex,
+    // Log of the committed operations
+    pub audit: AuditLog,
+}
+
+impl Validator {
+    // Empty validator with the given parameters. Withdraw fees, lockups and rounding other than floor are not
+    // supported, so they are rejected instead of being ignored
+    pub fn new(config: &ValidatorConfig) -> Result<Validator, ConfigError> {
+        let mut violations = match config.validate() {
+            Ok(()) => vec![],
+            Err(error) => error.violations,
+        };
+
+        if config.withdraw_fee_bps > 0 {
+            violations.push(ConfigViolation::Unsupported("withdraw_fee_bps"));
+        }
+        if config.lockup_rewards > 0 {
+            violations.push(ConfigViolation::Unsupported("lockup_rewards"));
+        }
+        if config.rounding != RoundingPolicy::Floor {
+            violations.push(ConfigViolation::Unsupported("rounding"));
+        }
+
+        if !violations.is_empty() {
+            return Err(ConfigError { violations });
+        }
+
+        Ok(Validator {
+            stakes: HashMap::new(),
+            total_delegated: 0,
+            total_balance: 0,
+            acc_reward_per_share: 0,
+            min_vote: config.min_vote,
+            max_total_delegated: config.max_total_delegated,
+            max_delegators: config.max_delegators,
+            paused: false,
+            min_payout: config.min_payout,
+            accrued: HashMap::new(),
+            state: ValidatorState::Active,
+            last_reward_id: None,
+            rewards_count: RewardIndex::default(),
+            audit: AuditLog::new(config.audit_capacity),
+        })
+    }
+
+    // Reward the user would get now
+    pub fn pending_reward(&self, address: Address) -> Amount {
+        match self.stakes.get(&address) {
+            Some(stake) => self
+                .accumulated(stake.amount)
+                .saturating_sub(stake.reward_debt),
+            None => 0,
+        }
+    }
+
+    // Accumulated reward of the amount over all rewards
+    fn accumulated(&self, amount: Amount) -> Amount {
+        amount * self.acc_reward_per_share / ACC_PRECISION
+    }
+
+    // Settled part of the accumulated reward of the amount. Rounded up, so users never get more than their part
+    fn reward_debt(&self, amount: Amount) -> Amount {
+        (amount * self.acc_reward_per_share).div_ceil(ACC_PRECISION)
+    }
+
+    // Check that a new delegator fits into the limit, existing ones can always vote
+    fn check_delegator(&self, address: Address) -> Result<(), DposError> {
+        if let Some(max_delegators) = self.max_delegators {
+            if !self.stakes.contains_key(&address) && self.stakes.len() >= max_delegators {
+                return Err(DposError::DelegatorLimitReached(max_delegators));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check that validator accepts the amount
+    fn check_vote_amount(&self, user: &User, amount: Amount) -> Result<(), DposError> {
+        // Check that validator accepts votes
+        if self.paused {
+            return Err(DposError::Paused);
+        }
+
+        // Check that validator is active
+        if self.state != ValidatorState::Active {
+            return Err(DposError::NotActive);
+        }
+
+        // Reject zero and dust votes
+        if amount < self.min_vote {
+            return Err(DposError::BelowMinVote(self.min_vote));
+        }
+
+        // User must have the amount
+        if amount > user.balance {
+            return Err(DposError::InsufficientBalance(user.balance));
+        }
+
+        // Check that the new delegated balance fits into the cap
+        if let Some(max_total_delegated) = self.max_total_delegated {
+            let available = max_total_delegated.saturating_sub(self.total_delegated);
+            if amount > available {
+                return Err(DposError::CapExceeded(available));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check that validator accepts the reward
+    fn check_reward(&self, reward_id: u64) -> Result<(), DposError> {
+        // Check that validator is not paused
+        if self.paused {
+            return Err(DposError::Paused);
+        }
+
+        // Check that validator is active
+        if self.state != ValidatorState::Active {
+            return Err(DposError::NotActive);
+        }
+
+        // Reject rewards that have already been appended
+        if self.last_reward_id.is_some_and(|last| reward_id <= last) {
+            return Err(DposError::DuplicateReward(reward_id));
+        }
+
+        Ok(())
+    }
+
+    // Delegate the amount, the stake earns the rewards appended from now on
+    pub fn vote(&mut self, user: &mut User, amount: Amount) {
+        // First check that user has no stake
+        if self.stakes.contains_key(&user.address) {
+            panic!("Unvote before revoting");
+        }
+
+        if let Err(error) = self.check_vote_amount(user, amount) {
+            panic!("{}", error);
+        }
+        if let Err(error) = self.check_delegator(user.address) {
+            panic!("{}", error);
+        }
+
+        // Insert new stake, the rewards accumulated before are not its
+        let reward_debt = self.reward_debt(amount);
+        self.stakes.insert(
+            user.address,
+            Stake {
+                amount,
+                reward_debt,
+            },
+        );
+
+        // Update balances: user, delegated, total
+        user.balance -= amount;
+        self.total_delegated += amount;
+        self.total_balance += amount;
+
+        self.audit.record(
+            AuditOp::Vote,
+            Some(user.address),
+            amount,
+            self.rewards_count.0,
+        );
+    }
+
+    // Unvote settles the pending reward and returns the stake
+    pub fn unvote(&mut self, user: &mut User) {
+        // Check that stake exists
+        if !self.stakes.contains_key(&user.address) {
+            panic!("Nothing to unvote")
+        }
+
+        // Settle the pending reward
+        self.send_rewards(user);
+
+        // Update balances: user, delegated and total
+        let stake = self.stakes.remove(&user.address).unwrap();
+        user.balance += stake.amount;
+        self.total_delegated -= stake.amount;
+        self.total_balance -= stake.amount;
+
+        self.audit.record(
+            AuditOp::Unvote,
+            Some(user.address),
+            stake.amount,
+            self.rewards_count.0,
+        );
+    }
+
+    // Adding stake settles the pending reward, so the added stake doesn't earn for the past rewards
+    pub fn add_stake(&mut self, user: &mut User, amount: Amount) {
+        // Check that stake exists
+        if !self.stakes.contains_key(&user.address) {
+            panic!("No vote to add stake to")
+        }
+
+        if let Err(error) = self.check_vote_amount(user, amount) {
+            panic!("{}", error);
+        }
+
+        // Settle the pending reward
+        self.send_rewards(user);
+
+        // Update stake: the whole new amount earns from now on
+        let stake_amount = self.stakes.get(&user.address).unwrap().amount + amount;
+        let reward_debt = self.reward_debt(stake_amount);
+        self.stakes.insert(
+            user.address,
+            Stake {
+                amount: stake_amount,
+                reward_debt,
+            },
+        );
+
+        // Update balances: user, delegated, total
+        user.balance -= amount;
+        self.total_delegated += amount;
+        self.total_balance += amount;
+
+        self.audit.record(
+            AuditOp::AddStake,
+            Some(user.address),
+            amount,
+            self.rewards_count.0,
+        );
+    }
+
+    // Share users part of the reward with the stakes
+    pub fn append_reward(&mut self, reward_id: u64, reward: Amount) {
+        if let Err(error) = self.check_reward(reward_id) {
+            panic!("{}", error);
+        }
+        self.last_reward_id = Some(reward_id);
+
+        // Update total balance
+        self.total_balance += reward;
+
+        // Share users part of the reward per delegated token, nothing is shared if nobody has delegated
+        if self.total_delegated > 0 {
+            self.acc_reward_per_share +=
+                reward * SHARE * ACC_PRECISION / (100 * self.total_delegated);
+        }
+        self.rewards_count = self.rewards_count.succ();
+
+        self.audit
+            .record(AuditOp::Reward, None, reward, self.rewards_count.0);
+    }
+
+    // Send the reward accumulated since the last settlement
+    pub fn send_rewards(&mut self, user: &mut User) {
+        // Check that stake exists
+        let stake = self.stakes.get(&user.address);
+        if stake.is_none() {
+            panic!("No vote to get rewards")
+        }
+
+        let stake = stake.unwrap();
+
+        // Settle the reward accumulated since the last settlement
+        let amount = stake.amount;
+        let reward = self.accumulated(amount).saturating_sub(stake.reward_debt);
+        let reward_debt = self.reward_debt(amount);
+        self.stakes.insert(
+            user.address,
+            Stake {
+                amount,
+                reward_debt,
+            },
+        );
+
+        // Add the reward to the accrued one and send it only if it reaches the minimal payout
+        let reward = reward + self.accrued.remove(&user.address).unwrap_or(0);
+        if reward > 0 && reward < self.min_payout {
+            self.accrued.insert(user.address, reward);
+        } else {
+            // Update user and total balances
+            user.balance += reward;
+            self.total_balance -= reward;
+        }
+
+        self.audit.record(
+            AuditOp::SendRewards,
+            Some(user.address),
+            reward,
+            self.rewards_count.0,
+        );
+    }
+
+    // Send the rewards accrued below the minimal payout
+    pub fn claim_accrued(&mut self, user: &mut User) {
+        // Check that there is accrued reward
+        let reward = self.accrued.remove(&user.address);
+        if reward.is_none() {
+            panic!("No accrued reward to claim")
+        }
+
+        let reward = reward.unwrap();
+
+        // Update user and total balances
+        user.balance += reward;
+        self.total_balance -= reward;
+
+        self.audit.record(
+            AuditOp::ClaimAccrued,
+            Some(user.address),
+            reward,
+            self.rewards_count.0,
+        );
+    }
+}
+
+impl Staking for Validator {
+    type User = User;
+
+    fn try_vote(&mut self, user: &mut User, amount: Amount) -> Result<(), DposError> {
+        if self.stakes.contains_key(&user.address) {
+            return Err(DposError::AlreadyVoted);
+        }
+        self.check_vote_amount(user, amount)?;
+        self.check_delegator(user.address)?;
+        self.vote(user, amount);
+
+        Ok(())
+    }
+
+    fn try_unvote(&mut self, user: &mut User) -> Result<(), DposError> {
+        if !self.stakes.contains_key(&user.address) {
+            return Err(DposError::NoVote);
+        }
+        self.unvote(user);
+
+        Ok(())
+    }
+
+    fn try_append_reward(&mut self, reward_id: u64, reward: Amount) -> Result<(), DposError> {
+        self.check_reward(reward_id)?;
+        self.append_reward(reward_id, reward);
+
+        Ok(())
+    }
+
+    fn try_claim_rewards(&mut self, user: &mut User) -> Result<(), DposError> {
+        if !self.stakes.contains_key(&user.address) {
+            return Err(DposError::NoVote);
+        }
+        self.send_rewards(user);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_impl;
+    use crate::Index;
+
+    // Next value of the xorshift sequence
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn payouts_match_the_vote_based_validator() {
+        for seed in 1..20 {
+            let mut state = seed;
+            let config = ValidatorConfig::default();
+            let mut acc = Validator::new(&config).unwrap();
+            let mut votes = new_impl::Validator::new(&config).unwrap();
+            let mut acc_users: Vec<User> = (1..4)
+                .map(|address| User {
+                    address,
+                    balance: 1_000_000,
+                })
+                .collect();
+            let mut vote_users: Vec<User> = (1..4)
+                .map(|address| User {
+                    address,
+                    balance: 1_000_000,
+                })
+                .collect();
+            // Settlements of each user, every one of them can round differently by a unit
+            let mut settlements = [0; 3];
+            let mut reward_id = 0;
+
+            for _ in 0..300 {
+                let value = next_random(&mut state);
+                let i = (value / 5 % 3) as usize;
+                let amount = (value / 15 % 1000 + 1) as Amount;
+
+                match value % 5 {
+                    0 if acc.stakes.contains_key(&acc_users[i].address) => {
+                        acc.add_stake(&mut acc_users[i], amount);
+                        votes.add_stake(&mut vote_users[i], amount);
+                        settlements[i] += 1;
+                    }
+                    0 => {
+                        acc.vote(&mut acc_users[i], amount);
+                        votes.vote(&mut vote_users[i], amount);
+                    }
+                    1 => {
+                        let result = acc.try_unvote(&mut acc_users[i]);
+                        assert_eq!(result, votes.try_unvote(&mut vote_users[i]));
+                        settlements[i] += 1;
+                    }
+                    2 => {
+                        let result = acc.try_claim_rewards(&mut acc_users[i]);
+                        assert_eq!(result, votes.try_claim_rewards(&mut vote_users[i]));
+                        settlements[i] += 1;
+                    }
+                    _ => {
+                        acc.append_reward(reward_id, amount * 10);
+                        votes.append_reward(reward_id, amount * 10);
+                        reward_id += 1;
+                    }
+                }
+            }
+
+            for i in 0..3 {
+                let (acc_balance, vote_balance) = (acc_users[i].balance, vote_users[i].balance);
+                assert!(
+                    acc_balance.abs_diff(vote_balance) <= settlements[i],
+                    "user {}: {} != {}",
+                    i,
+                    acc_balance,
+                    vote_balance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn vote_and_reward_are_audited_in_order() {
+        let mut validator = Validator::new(&ValidatorConfig::default()).unwrap();
+        let mut alice = User {
+            address: 1,
+            balance: 100,
+        };
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.unvote(&mut alice);
+
+        let logged: Vec<(u64, AuditOp, Option<Address>, Amount, Index)> = validator
+            .audit
+            .since(0)
+            .iter()
+            .map(|entry| {
+                (
+                    entry.seq,
+                    entry.op,
+                    entry.address,
+                    entry.amount,
+                    entry.index,
+                )
+            })
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                (0, AuditOp::Vote, Some(1), 100, 0),
+                (1, AuditOp::Reward, None, 1000, 1),
+                (2, AuditOp::SendRewards, Some(1), 300, 1),
+                (3, AuditOp::Unvote, Some(1), 100, 1),
+            ]
+        );
+    }
+}
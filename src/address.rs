@@ -0,0 +1,127 @@
+use std::fmt;
+
+use serde::de;
+use serde::{Deserialize, Deserializer, Serializer};
+
+use super::Address;
+
+// Address length in bytes
+pub const ADDRESS_BYTES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressError {
+    // Address is not prefixed with 0x
+    MissingPrefix,
+    // Address has a wrong number of bytes
+    InvalidLength(usize),
+    // Address contains a non-hex character
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::MissingPrefix => write!(f, "Address must start with 0x"),
+            AddressError::InvalidLength(length) => write!(
+                f,
+                "Address must be {} bytes, got {} hex digits",
+                ADDRESS_BYTES, length
+            ),
+            AddressError::InvalidCharacter(c) => write!(f, "Invalid hex character {:?}", c),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+// Parse a 0x prefixed hex address
+pub fn parse(s: &str) -> Result<Address, AddressError> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .ok_or(AddressError::MissingPrefix)?;
+
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(AddressError::InvalidCharacter(c));
+    }
+    if digits.len() != ADDRESS_BYTES * 2 {
+        return Err(AddressError::InvalidLength(digits.len()));
+    }
+
+    Ok(Address::from_str_radix(digits, 16).unwrap())
+}
+
+// Format an address as 0x prefixed hex with all leading zeros
+pub fn to_hex(address: &Address) -> String {
+    format!("0x{:0width$x}", address, width = ADDRESS_BYTES * 2)
+}
+
+// Serialize an address as 0x prefixed hex, used with #[serde(with = "address")]
+pub fn serialize<S: Serializer>(address: &Address, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(address))
+}
+
+// Deserialize a 0x prefixed hex address
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse(&s).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_impl::User;
+
+    #[test]
+    fn parses_valid_addresses() {
+        assert_eq!(parse("0x00000000000000000000000000000001"), Ok(1));
+        assert_eq!(
+            parse("0XFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"),
+            Ok(Address::MAX)
+        );
+        assert_eq!(
+            parse("0x0123456789abcdefABCDEF0123456789"),
+            Ok(0x0123456789abcdefabcdef0123456789)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert_eq!(parse("0x01"), Err(AddressError::InvalidLength(2)));
+        assert_eq!(
+            parse("0x000000000000000000000000000000000001"),
+            Err(AddressError::InvalidLength(36))
+        );
+        assert_eq!(
+            parse("0x0000000000000000000000000000000g"),
+            Err(AddressError::InvalidCharacter('g'))
+        );
+        assert_eq!(
+            parse("00000000000000000000000000000001"),
+            Err(AddressError::MissingPrefix)
+        );
+    }
+
+    #[test]
+    fn formats_with_leading_zeros() {
+        assert_eq!(to_hex(&1), "0x00000000000000000000000000000001");
+        assert_eq!(parse(&to_hex(&(1 << 64))), Ok(1 << 64));
+    }
+
+    #[test]
+    fn users_serialize_hex_addresses() {
+        let user = User {
+            address: 1 << 64,
+            balance: 5,
+        };
+        let json = serde_json::to_string(&user).unwrap();
+        assert_eq!(
+            json,
+            r#"{"address":"0x00000000000000010000000000000000","balance":5}"#
+        );
+
+        let restored: User = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.address, user.address);
+        assert!(serde_json::from_str::<User>(r#"{"address":"0x01","balance":5}"#).is_err());
+    }
+}
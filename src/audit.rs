@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::unix_time;
+use super::Address;
+use super::Amount;
+use super::Index;
+
+// Default number of entries kept in the audit log
+pub const DEFAULT_AUDIT_CAPACITY: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditOp {
+    Vote,
+    AddStake,
+    Unvote,
+    Reward,
+    Withdraw,
+    SendRewards,
+    ClaimAccrued,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    // Sequence number of the entry, increasing
+    pub seq: u64,
+    // Operation
+    pub op: AuditOp,
+    // User address, none for validator-wide operations
+    pub address: Option<Address>,
+    // Amount the operation has moved
+    pub amount: Amount,
+    // Index or rewards count after the operation
+    pub index: Index,
+    // Unix time of the operation in seconds
+    pub timestamp: u64,
+}
+
+// Append-only log of committed operations, the oldest entries are dropped once it is full
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditLog {
+    // Maximum number of kept entries
+    pub capacity: usize,
+    // Kept entries ordered by sequence number
+    pub entries: VecDeque<AuditEntry>,
+    // Sequence number of the next entry
+    pub next_seq: u64,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> AuditLog {
+        AuditLog {
+            capacity,
+            entries: VecDeque::new(),
+            next_seq: 0,
+        }
+    }
+
+    // Record an operation, must be called once it has been applied
+    pub fn record(&mut self, op: AuditOp, address: Option<Address>, amount: Amount, index: Index) {
+        self.entries.push_back(AuditEntry {
+            seq: self.next_seq,
+            op,
+            address,
+            amount,
+            index,
+            timestamp: unix_time(),
+        });
+        self.next_seq += 1;
+
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    // Kept entries with sequence numbers from since on
+    pub fn since(&self, since: u64) -> Vec<AuditEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.seq >= since)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for AuditLog {
+    fn default() -> AuditLog {
+        AuditLog::new(DEFAULT_AUDIT_CAPACITY)
+    }
+}
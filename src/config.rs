@@ -0,0 +1,323 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::audit::DEFAULT_AUDIT_CAPACITY;
+use super::old_impl::{RewardMode, INDEX_MAX_DELTA};
+use super::Amount;
+use super::Index;
+use super::RoundingPolicy;
+use super::DEFAULT_MIN_VOTE;
+use super::MAX_WITHDRAW_FEE_BPS;
+use super::MULTIPLIER_BASE;
+
+// Parameters a validator is created with, shared by all implementations. Parameters of one implementation only
+// are ignored by the others, the accumulator-based validator rejects the shared ones it does not have
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorConfig {
+    // Minimal amount of a single vote
+    pub min_vote: Amount,
+    // Maximum total support, unlimited if none
+    pub max_total_delegated: Option<Amount>,
+    // Maximum number of delegators, unlimited if none
+    pub max_delegators: Option<usize>,
+    // Fee on withdrawn principal in basis points
+    pub withdraw_fee_bps: u32,
+    // Number of rewards the support stays locked for
+    pub lockup_rewards: Index,
+    // Rounding of user rewards
+    pub rounding: RoundingPolicy,
+    // Maximum number of indexes processed in one request (index-based validator)
+    pub max_index_delta: Index,
+    // Support amount from which a user gets the tier multiplier, no tiers if none (index-based validator)
+    pub tier_threshold: Option<Amount>,
+    // Reward multiplier for the users above the tier threshold (index-based validator)
+    pub tier_rate: Amount,
+    // Reward of the support at the index it is deposited at (index-based validator)
+    pub reward_mode: RewardMode,
+    // Minimal reward that gets its own index (index-based validator)
+    pub min_reward: Amount,
+    // Number of rewards per year used to annualize the estimated APR (index-based validator)
+    pub rewards_per_year: u32,
+    // Minimal reward to send to a user (vote-based validator)
+    pub min_payout: Amount,
+    // Number of entries kept in the audit log
+    pub audit_capacity: usize,
+}
+
+impl Default for ValidatorConfig {
+    fn default() -> ValidatorConfig {
+        ValidatorConfig {
+            min_vote: DEFAULT_MIN_VOTE,
+            max_total_delegated: None,
+            max_delegators: None,
+            withdraw_fee_bps: 0,
+            lockup_rewards: 0,
+            rounding: RoundingPolicy::default(),
+            max_index_delta: INDEX_MAX_DELTA,
+            tier_threshold: None,
+            tier_rate: MULTIPLIER_BASE,
+            reward_mode: RewardMode::default(),
+            min_reward: 0,
+            rewards_per_year: 0,
+            min_payout: 0,
+            audit_capacity: DEFAULT_AUDIT_CAPACITY,
+        }
+    }
+}
+
+impl ValidatorConfig {
+    // Builder starting from the default config
+    pub fn builder() -> ValidatorBuilder {
+        ValidatorBuilder {
+            config: ValidatorConfig::default(),
+        }
+    }
+
+    // Check all constraints, the error lists every violated one
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut violations = vec![];
+
+        if self.min_vote == 0 {
+            violations.push(ConfigViolation::ZeroMinVote);
+        }
+
+        if let Some(max_total_delegated) = self.max_total_delegated {
+            if self.min_vote > max_total_delegated {
+                violations.push(ConfigViolation::MinVoteAboveCap {
+                    min_vote: self.min_vote,
+                    max_total_delegated,
+                });
+            }
+        }
+
+        if self.withdraw_fee_bps > MAX_WITHDRAW_FEE_BPS {
+            violations.push(ConfigViolation::WithdrawFeeTooHigh(self.withdraw_fee_bps));
+        }
+
+        if self.max_index_delta == 0 {
+            violations.push(ConfigViolation::ZeroIndexDelta);
+        }
+
+        if self.tier_threshold.is_some() && self.tier_rate < MULTIPLIER_BASE {
+            violations.push(ConfigViolation::TierRateBelowBase(self.tier_rate));
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { violations })
+        }
+    }
+}
+
+pub struct ValidatorBuilder {
+    config: ValidatorConfig,
+}
+
+impl ValidatorBuilder {
+    pub fn min_vote(mut self, min_vote: Amount) -> ValidatorBuilder {
+        self.config.min_vote = min_vote;
+        self
+    }
+
+    pub fn max_total_delegated(mut self, max_total_delegated: Amount) -> ValidatorBuilder {
+        self.config.max_total_delegated = Some(max_total_delegated);
+        self
+    }
+
+    pub fn max_delegators(mut self, max_delegators: usize) -> ValidatorBuilder {
+        self.config.max_delegators = Some(max_delegators);
+        self
+    }
+
+    pub fn withdraw_fee_bps(mut self, withdraw_fee_bps: u32) -> ValidatorBuilder {
+        self.config.withdraw_fee_bps = withdraw_fee_bps;
+        self
+    }
+
+    pub fn lockup_rewards(mut self, lockup_rewards: Index) -> ValidatorBuilder {
+        self.config.lockup_rewards = lockup_rewards;
+        self
+    }
+
+    pub fn rounding(mut self, rounding: RoundingPolicy) -> ValidatorBuilder {
+        self.config.rounding = rounding;
+        self
+    }
+
+    pub fn max_index_delta(mut self, max_index_delta: Index) -> ValidatorBuilder {
+        self.config.max_index_delta = max_index_delta;
+        self
+    }
+
+    pub fn tier(mut self, threshold: Amount, rate: Amount) -> ValidatorBuilder {
+        self.config.tier_threshold = Some(threshold);
+        self.config.tier_rate = rate;
+        self
+    }
+
+    pub fn reward_mode(mut self, reward_mode: RewardMode) -> ValidatorBuilder {
+        self.config.reward_mode = reward_mode;
+        self
+    }
+
+    pub fn min_reward(mut self, min_reward: Amount) -> ValidatorBuilder {
+        self.config.min_reward = min_reward;
+        self
+    }
+
+    pub fn rewards_per_year(mut self, rewards_per_year: u32) -> ValidatorBuilder {
+        self.config.rewards_per_year = rewards_per_year;
+        self
+    }
+
+    pub fn min_payout(mut self, min_payout: Amount) -> ValidatorBuilder {
+        self.config.min_payout = min_payout;
+        self
+    }
+
+    pub fn audit_capacity(mut self, audit_capacity: usize) -> ValidatorBuilder {
+        self.config.audit_capacity = audit_capacity;
+        self
+    }
+
+    // Validated config
+    pub fn build(self) -> Result<ValidatorConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigViolation {
+    // Zero votes would be accepted
+    ZeroMinVote,
+    // No vote would fit into the cap
+    MinVoteAboveCap {
+        min_vote: Amount,
+        max_total_delegated: Amount,
+    },
+    // Withdraw fee is above MAX_WITHDRAW_FEE_BPS
+    WithdrawFeeTooHigh(u32),
+    // Withdrawals would never process an index
+    ZeroIndexDelta,
+    // Users above the tier threshold would get less than the others
+    TierRateBelowBase(Amount),
+    // Parameter is set but the implementation does not have it
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigViolation::ZeroMinVote => write!(f, "Minimal vote must not be zero"),
+            ConfigViolation::MinVoteAboveCap {
+                min_vote,
+                max_total_delegated,
+            } => write!(
+                f,
+                "Minimal vote {} is above the delegation cap {}",
+                min_vote, max_total_delegated
+            ),
+            ConfigViolation::WithdrawFeeTooHigh(fee_bps) => write!(
+                f,
+                "Withdraw fee {} bps is above the maximum of {} bps",
+                fee_bps, MAX_WITHDRAW_FEE_BPS
+            ),
+            ConfigViolation::ZeroIndexDelta => write!(f, "Max index delta must not be zero"),
+            ConfigViolation::TierRateBelowBase(rate) => write!(
+                f,
+                "Tier rate {} is below the multiplier base {}",
+                rate, MULTIPLIER_BASE
+            ),
+            ConfigViolation::Unsupported(parameter) => {
+                write!(f, "Parameter {} is not supported", parameter)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    // Every violated constraint
+    pub violations: Vec<ConfigViolation>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let violations: Vec<String> = self.violations.iter().map(|v| v.to_string()).collect();
+        write!(f, "Invalid validator config: {}", violations.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        let config = ValidatorConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(ValidatorConfig::builder().build(), Ok(config.clone()));
+
+        assert_eq!(config.min_vote, DEFAULT_MIN_VOTE);
+        assert_eq!(config.max_total_delegated, None);
+        assert_eq!(config.max_delegators, None);
+        assert_eq!(config.withdraw_fee_bps, 0);
+        assert_eq!(config.lockup_rewards, 0);
+        assert_eq!(config.rounding, RoundingPolicy::Floor);
+        assert_eq!(config.max_index_delta, INDEX_MAX_DELTA);
+        assert_eq!(config.tier_threshold, None);
+        assert_eq!(config.audit_capacity, DEFAULT_AUDIT_CAPACITY);
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let error = ValidatorConfig::builder()
+            .min_vote(0)
+            .withdraw_fee_bps(MAX_WITHDRAW_FEE_BPS + 1)
+            .max_index_delta(0)
+            .tier(1000, MULTIPLIER_BASE - 1)
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            error.violations,
+            vec![
+                ConfigViolation::ZeroMinVote,
+                ConfigViolation::WithdrawFeeTooHigh(MAX_WITHDRAW_FEE_BPS + 1),
+                ConfigViolation::ZeroIndexDelta,
+                ConfigViolation::TierRateBelowBase(MULTIPLIER_BASE - 1),
+            ]
+        );
+        assert!(error
+            .to_string()
+            .starts_with("Invalid validator config: Minimal vote must not be zero; "));
+    }
+
+    #[test]
+    fn min_vote_must_fit_into_the_cap() {
+        assert_eq!(
+            ValidatorConfig::builder()
+                .min_vote(500)
+                .max_total_delegated(100)
+                .build(),
+            Err(ConfigError {
+                violations: vec![ConfigViolation::MinVoteAboveCap {
+                    min_vote: 500,
+                    max_total_delegated: 100
+                }]
+            })
+        );
+
+        // The cap can hold exactly one minimal vote
+        assert!(ValidatorConfig::builder()
+            .min_vote(100)
+            .max_total_delegated(100)
+            .build()
+            .is_ok());
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::new_impl::Validator;
+use super::Address;
+use super::Amount;
+use super::Index;
+use super::RewardIndex;
+
+pub type ProposalId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Choice {
+    Yes,
+    No,
+    Abstain,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    // Total weight of yes choices
+    pub yes: Amount,
+    // Total weight of no choices
+    pub no: Amount,
+    // Total weight of abstain choices
+    pub abstain: Amount,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Proposal {
+    // Proposal id
+    pub id: ProposalId,
+    // Hash of the proposal description
+    pub description_hash: u128,
+    // Rewards count when the proposal has been created, voting weights are taken at that moment
+    pub start_index: RewardIndex,
+    // Last rewards count when votes are accepted
+    pub end_index: RewardIndex,
+    // Voting weights: delegated amounts at the start index
+    pub weights: HashMap<Address, Amount>,
+    // Choices of the delegators
+    pub choices: HashMap<Address, Choice>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Governance {
+    // Proposals by their ids
+    pub proposals: HashMap<ProposalId, Proposal>,
+    // Id of the next proposal
+    pub next_id: ProposalId,
+}
+
+impl Governance {
+    // Create a proposal open for voting until duration more rewards are appended to the validator
+    pub fn create_proposal(
+        &mut self,
+        validator: &Validator,
+        description_hash: u128,
+        duration: Index,
+    ) -> ProposalId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        // Snapshot the voting weights, later votes and unvotes don't change them
+        let weights = validator
+            .votes
+            .iter()
+            .filter(|(_, vote)| vote.amount > 0)
+            .map(|(address, vote)| (*address, vote.amount))
+            .collect();
+
+        self.proposals.insert(
+            id,
+            Proposal {
+                id,
+                description_hash,
+                start_index: validator.rewards_count,
+                end_index: validator.rewards_count.after(duration),
+                weights,
+                choices: HashMap::new(),
+            },
+        );
+
+        id
+    }
+
+    // Cast or replace the delegator choice
+    pub fn cast_vote(
+        &mut self,
+        validator: &Validator,
+        user_address: Address,
+        proposal_id: ProposalId,
+        choice: Choice,
+    ) {
+        let proposal = self.proposals.get_mut(&proposal_id);
+        if proposal.is_none() {
+            panic!("No proposal {}", proposal_id);
+        }
+
+        let proposal = proposal.unwrap();
+
+        if validator.rewards_count > proposal.end_index {
+            panic!("Voting for proposal {} has ended", proposal_id);
+        }
+
+        if !proposal.weights.contains_key(&user_address) {
+            panic!("No delegation at the proposal start");
+        }
+
+        proposal.choices.insert(user_address, choice);
+    }
+
+    // Weights of every choice
+    pub fn tally(&self, proposal_id: ProposalId) -> Tally {
+        let proposal = self.proposals.get(&proposal_id);
+        if proposal.is_none() {
+            panic!("No proposal {}", proposal_id);
+        }
+
+        let proposal = proposal.unwrap();
+
+        let mut tally = Tally::default();
+        for (address, choice) in proposal.choices.iter() {
+            let weight = proposal.weights.get(address).cloned().unwrap_or(0);
+            match choice {
+                Choice::Yes => tally.yes += weight,
+                Choice::No => tally.no += weight,
+                Choice::Abstain => tally.abstain += weight,
+            }
+        }
+
+        tally
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ValidatorConfig;
+    use crate::new_impl::User;
+
+    fn validator(config: ValidatorConfig) -> Validator {
+        Validator::new(&config).unwrap()
+    }
+
+    fn user(address: Address, balance: Amount) -> User {
+        User { address, balance }
+    }
+
+    #[test]
+    fn weights_are_taken_at_the_proposal_start() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 300);
+        let mut bob = user(2, 100);
+        validator.vote(&mut alice, 300);
+        validator.vote(&mut bob, 100);
+
+        let mut governance = Governance::default();
+        let id = governance.create_proposal(&validator, 0xabc, 2);
+
+        // Alice leaves mid-proposal and a new delegator comes, the weights stay
+        validator.unvote(&mut alice);
+        validator.vote(&mut user(3, 500), 500);
+        validator.append_reward(0, 1000);
+
+        governance.cast_vote(&validator, 1, id, Choice::Yes);
+        governance.cast_vote(&validator, 2, id, Choice::No);
+        assert_eq!(
+            governance.tally(id),
+            Tally {
+                yes: 300,
+                no: 100,
+                abstain: 0
+            }
+        );
+    }
+
+    #[test]
+    fn repeated_vote_replaces_the_choice() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 300), 300);
+
+        let mut governance = Governance::default();
+        let id = governance.create_proposal(&validator, 0xabc, 2);
+        governance.cast_vote(&validator, 1, id, Choice::Yes);
+        governance.cast_vote(&validator, 1, id, Choice::Abstain);
+
+        assert_eq!(
+            governance.tally(id),
+            Tally {
+                yes: 0,
+                no: 0,
+                abstain: 300
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Voting for proposal 0 has ended")]
+    fn votes_after_the_end_index_are_rejected() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 300), 300);
+
+        let mut governance = Governance::default();
+        let id = governance.create_proposal(&validator, 0xabc, 1);
+        validator.append_reward(0, 1000);
+        governance.cast_vote(&validator, 1, id, Choice::Yes);
+        validator.append_reward(1, 1000);
+        governance.cast_vote(&validator, 1, id, Choice::No);
+    }
+
+    #[test]
+    #[should_panic(expected = "No delegation at the proposal start")]
+    fn later_delegators_can_not_vote() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 300), 300);
+
+        let mut governance = Governance::default();
+        let id = governance.create_proposal(&validator, 0xabc, 1);
+        validator.vote(&mut user(2, 100), 100);
+        governance.cast_vote(&validator, 2, id, Choice::Yes);
+    }
+}
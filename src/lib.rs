@@ -1,8 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod acc_impl;
+pub mod address;
+pub mod audit;
+pub mod config;
+pub mod governance;
+pub mod migrate;
 pub mod new_impl;
 pub mod old_impl;
+pub mod traits;
 
 pub const SHARE: u128 = 30;
 
+// Fixed-point base of reward multipliers: 100 is 1.0
+pub const MULTIPLIER_BASE: u128 = 100;
+
+// Default minimal amount of a single vote
+pub const DEFAULT_MIN_VOTE: Amount = 1;
+
+// Dust is measured in 1 / DUST_PRECISION fractions of a token
+pub const DUST_PRECISION: u128 = 1_000_000;
+
+// Maximum fee on withdrawn principal in basis points
+pub const MAX_WITHDRAW_FEE_BPS: u32 = 500;
+
 pub type Amount = u128;
 pub type Address = u128;
 pub type Index = u32;
+
+// Sequence number of a reward, kept apart from counts so that only indexes are compared with each other
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct RewardIndex(pub Index);
+
+impl RewardIndex {
+    // Index of the next reward
+    pub fn succ(self) -> RewardIndex {
+        RewardIndex(self.0 + 1)
+    }
+
+    // Index count rewards after this one
+    pub fn after(self, count: Index) -> RewardIndex {
+        RewardIndex(self.0 + count)
+    }
+
+    // Index count rewards before this one, the first index at most
+    pub fn before(self, count: Index) -> RewardIndex {
+        RewardIndex(self.0.saturating_sub(count))
+    }
+
+    // Indexes from this one up to the end one, the end one excluded
+    pub fn until(self, end: RewardIndex) -> impl Iterator<Item = RewardIndex> {
+        (self.0..end.0).map(RewardIndex)
+    }
+
+    // Number of rewards from the earlier index to this one
+    pub fn delta(self, earlier: RewardIndex) -> Index {
+        if earlier > self {
+            panic!("Reward index {} is after {}", earlier.0, self.0);
+        }
+
+        self.0 - earlier.0
+    }
+}
+
+impl fmt::Display for RewardIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Lifecycle state of a validator
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ValidatorState {
+    #[default]
+    Active,
+    // Votes and rewards are not accepted, users withdraw their support
+    Deactivating,
+    // Everything has been withdrawn
+    Closed,
+}
+
+// Rounding of reward splits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RoundingPolicy {
+    #[default]
+    Floor,
+    Ceil,
+    // Round half to even
+    Bankers,
+}
+
+impl RoundingPolicy {
+    // Divide with rounding. Returns the quotient and the rounding remainder in DUST_PRECISION fractions of a token:
+    // positive if the quotient has been rounded down and negative if up. Nothing is shared by zero denominator
+    pub fn divide(self, numerator: Amount, denominator: Amount) -> (Amount, i128) {
+        if denominator == 0 {
+            return (0, 0);
+        }
+
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+
+        let rounded = match self {
+            RoundingPolicy::Floor => quotient,
+            RoundingPolicy::Ceil if remainder > 0 => quotient + 1,
+            RoundingPolicy::Ceil => quotient,
+            RoundingPolicy::Bankers => {
+                if 2 * remainder > denominator
+                    || (2 * remainder == denominator && quotient % 2 == 1)
+                {
+                    quotient + 1
+                } else {
+                    quotient
+                }
+            }
+        };
+
+        // Fraction of the remainder in DUST_PRECISION units. The remainder is below the denominator, so only
+        // denominators close to the amount limit need to be scaled down first
+        let fraction = match remainder.checked_mul(DUST_PRECISION) {
+            Some(scaled) => scaled / denominator,
+            None => remainder / (denominator / DUST_PRECISION),
+        } as i128;
+
+        if rounded > quotient {
+            (rounded, fraction - DUST_PRECISION as i128)
+        } else {
+            (rounded, fraction)
+        }
+    }
+}
+
+// Fee on withdrawn principal, rounded down in favour of the user
+pub fn withdraw_fee(amount: Amount, fee_bps: u32) -> Amount {
+    // No fee - no rounding
+    if fee_bps == 0 {
+        return 0;
+    }
+
+    amount * fee_bps as Amount / 10_000
+}
+
+// Current unix time in seconds
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// Add rounding remainder to the dust. Dust can't go below zero if rewards have been rounded up
+pub fn add_dust(dust: Amount, remainder: i128) -> Amount {
+    cmp::max(dust as i128 + remainder, 0) as Amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divide_keeps_the_remainder_as_dust() {
+        assert_eq!(RoundingPolicy::Floor.divide(7, 2), (3, 500_000));
+        assert_eq!(RoundingPolicy::Ceil.divide(7, 2), (4, -500_000));
+        assert_eq!(RoundingPolicy::Bankers.divide(7, 2), (4, -500_000));
+        assert_eq!(RoundingPolicy::Bankers.divide(5, 2), (2, 500_000));
+        assert_eq!(RoundingPolicy::Floor.divide(6, 2), (3, 0));
+        assert_eq!(RoundingPolicy::Floor.divide(6, 0), (0, 0));
+    }
+
+    #[test]
+    fn divide_does_not_overflow_on_large_amounts() {
+        let numerator = 10u128.pow(36) + 1;
+        assert_eq!(
+            RoundingPolicy::Floor.divide(numerator, 10u128.pow(12)),
+            (10u128.pow(24), 0)
+        );
+
+        // Remainder 1 of the largest denominators can't be scaled up
+        assert_eq!(
+            RoundingPolicy::Ceil.divide(u128::MAX, u128::MAX / 2),
+            (3, -(DUST_PRECISION as i128))
+        );
+    }
+
+    #[test]
+    fn reward_index_delta() {
+        assert_eq!(RewardIndex(5).delta(RewardIndex(3)), 2);
+        assert_eq!(RewardIndex(3).delta(RewardIndex(3)), 0);
+        assert_eq!(RewardIndex(3).after(2), RewardIndex(5));
+        assert_eq!(RewardIndex(3).succ(), RewardIndex(4));
+        assert_eq!(RewardIndex(3).before(2), RewardIndex(1));
+        assert_eq!(RewardIndex(3).before(5), RewardIndex(0));
+        assert_eq!(
+            RewardIndex(1).until(RewardIndex(4)).collect::<Vec<_>>(),
+            vec![RewardIndex(1), RewardIndex(2), RewardIndex(3)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Reward index 5 is after 3")]
+    fn reward_index_delta_underflow_is_caught() {
+        RewardIndex(3).delta(RewardIndex(5));
+    }
+}
@@ -0,0 +1,278 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::audit::AuditLog;
+use super::new_impl;
+use super::new_impl::{User, Vote};
+use super::old_impl;
+use super::Address;
+use super::Amount;
+use super::RewardIndex;
+
+// What happens to the rewards users have not withdrawn from the old validator yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PendingRewards {
+    // Rewards are sent to the user balances
+    Pay,
+    // Rewards are added to the vote amounts
+    Compound,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    // Validator balance doesn't cover the users support and pending rewards
+    Unreconciled { required: Amount, available: Amount },
+    // Rewards must be paid to a user that has not been supplied
+    MissingUser(Address),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Unreconciled {
+                required,
+                available,
+            } => write!(
+                f,
+                "Users support and rewards require {}, only {} available",
+                required, available
+            ),
+            MigrationError::MissingUser(address) => write!(f, "No user {} to pay to", address),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+// Vote-based validator with the state of the index-based one: support of every user at all indexes becomes
+// a single vote counting rewards from now on, it unlocks at the latest unlock index of that support. Rewards
+// that users have not withdrawn yet are either paid to the supplied users or added to their votes. Nothing is
+// changed if an error is returned
+pub fn old_to_new(
+    old: &old_impl::Validator,
+    pending_rewards: PendingRewards,
+    users: &mut HashMap<Address, User>,
+) -> Result<new_impl::Validator, MigrationError> {
+    // Support, pending reward and unlock index of every user
+    let positions: Vec<(Address, Amount, Amount, RewardIndex)> = old
+        .user_support_indexes
+        .iter()
+        .map(|(address, indexes)| {
+            let statement = old.user_statement(*address);
+            let supported = statement.entries.iter().map(|entry| entry.supported).sum();
+            let unlocks_at = indexes
+                .iter()
+                .map(|index| old.unlock_index(*address, *index))
+                .max()
+                .unwrap_or_default();
+            (*address, supported, statement.pending_reward, unlocks_at)
+        })
+        .collect();
+
+    // Check everything before any change
+    let required: Amount = positions
+        .iter()
+        .map(|(_, supported, reward, _)| supported + reward)
+        .sum();
+    if required > old.total_balance {
+        return Err(MigrationError::Unreconciled {
+            required,
+            available: old.total_balance,
+        });
+    }
+    if pending_rewards == PendingRewards::Pay {
+        for (address, _, _, _) in positions.iter() {
+            if !users.contains_key(address) {
+                return Err(MigrationError::MissingUser(*address));
+            }
+        }
+    }
+
+    let mut votes = HashMap::new();
+    let mut total_delegated = 0;
+    let mut total_balance = old.total_balance;
+    for (address, supported, reward, unlocks_at) in positions {
+        let amount = match pending_rewards {
+            PendingRewards::Pay => {
+                users.get_mut(&address).unwrap().balance += reward;
+                total_balance -= reward;
+                supported
+            }
+            PendingRewards::Compound => supported + reward,
+        };
+
+        // The vote keeps the rest of the lockup: it is counted from the index that unlocks at the same time
+        let first_reward_id = cmp::min(unlocks_at.before(old.lockup_rewards), old.current_index);
+
+        total_delegated += amount;
+        votes.insert(
+            address,
+            Vote {
+                first_reward_id,
+                amount,
+                last_claimed_reward_id: old.current_index,
+            },
+        );
+    }
+
+    Ok(new_impl::Validator {
+        votes,
+        total_delegated,
+        // The rest stays on the balance: undistributed rewards, dust and fees
+        total_balance,
+        rewards_count: old.current_index,
+        // No rewards have been shared before the current index
+        reward_per_unit: vec![0; old.current_index.0 as usize + 1],
+        min_vote: old.min_vote,
+        max_total_delegated: old.max_total_delegated,
+        max_delegators: old.max_delegators,
+        paused: old.paused,
+        min_payout: 0,
+        accrued: HashMap::new(),
+        auto_compound: old.auto_compound.clone(),
+        rounding: old.rounding,
+        dust: old.dust,
+        state: old.state,
+        last_reward_id: old.last_reward_id,
+        lockup_rewards: old.lockup_rewards,
+        withdraw_fee_bps: old.withdraw_fee_bps,
+        collected_fees: old.collected_fees,
+        audit: AuditLog::new(old.audit.capacity),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ValidatorConfig;
+    use crate::traits::{DposError, Staking};
+
+    const ALICE: Address = 1 << 64;
+    const BOB: Address = 2 << 64;
+
+    // Index-based validator where alice and bob have support at several indexes and bob has withdrawn once
+    fn old_validator(config: ValidatorConfig) -> (old_impl::Validator, HashMap<Address, User>) {
+        let mut old = old_impl::Validator::new(&config).unwrap();
+        let mut alice = old_impl::User {
+            address: ALICE,
+            balance: 1000,
+        };
+        let mut bob = old_impl::User {
+            address: BOB,
+            balance: 1000,
+        };
+
+        old.vote(&mut alice, 100);
+        old.vote(&mut bob, 300);
+        old.append_reward(0, 1000);
+        old.vote(&mut alice, 50);
+        old.append_reward(1, 777);
+        old.try_claim_rewards(&mut bob).unwrap();
+        old.append_reward(2, 333);
+
+        let users = [alice, bob]
+            .iter()
+            .map(|user| {
+                let new_user = User {
+                    address: user.address,
+                    balance: user.balance,
+                };
+                (user.address, new_user)
+            })
+            .collect();
+        (old, users)
+    }
+
+    // Tokens of the users and the validator together
+    fn total(validator: &new_impl::Validator, users: &HashMap<Address, User>) -> Amount {
+        validator.total_balance + users.values().map(|user| user.balance).sum::<Amount>()
+    }
+
+    #[test]
+    fn migration_conserves_stake_and_balances() {
+        for pending_rewards in [PendingRewards::Pay, PendingRewards::Compound] {
+            let (old, mut users) = old_validator(ValidatorConfig::default());
+            let before =
+                old.total_balance + users.values().map(|user| user.balance).sum::<Amount>();
+            let supported = old.support_at(old.current_index);
+            assert_eq!(supported, old.stake_of(ALICE) + old.stake_of(BOB));
+
+            let mut new = old_to_new(&old, pending_rewards, &mut users).unwrap();
+            assert_eq!(total(&new, &users), before);
+            let rewards =
+                old.user_statement(ALICE).pending_reward + old.user_statement(BOB).pending_reward;
+            match pending_rewards {
+                PendingRewards::Pay => assert_eq!(new.total_delegated, supported),
+                PendingRewards::Compound => assert_eq!(new.total_delegated, supported + rewards),
+            }
+
+            // Everything left after the users withdraw is what has not been theirs
+            for address in [ALICE, BOB] {
+                new.try_unvote(users.get_mut(&address).unwrap()).unwrap();
+            }
+            assert_eq!(total(&new, &users), before);
+            assert_eq!(new.total_balance, old.total_balance - supported - rewards);
+        }
+    }
+
+    #[test]
+    fn unlocked_position_stays_unlocked() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(2)
+            .build()
+            .unwrap();
+        let mut old = old_impl::Validator::new(&config).unwrap();
+        let mut alice = old_impl::User {
+            address: ALICE,
+            balance: 100,
+        };
+        old.vote(&mut alice, 100);
+        old.append_rewards((0..3).map(|reward_id| (reward_id, 1000)).collect());
+
+        let mut users = HashMap::new();
+        users.insert(
+            ALICE,
+            User {
+                address: ALICE,
+                balance: 0,
+            },
+        );
+        let mut new = old_to_new(&old, PendingRewards::Pay, &mut users).unwrap();
+        let alice = users.get_mut(&ALICE).unwrap();
+        assert_eq!(new.try_unvote(alice), Ok(()));
+        assert_eq!(alice.balance, 100 + 900);
+    }
+
+    #[test]
+    fn locked_position_keeps_the_rest_of_its_lockup() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(5)
+            .build()
+            .unwrap();
+        let mut old = old_impl::Validator::new(&config).unwrap();
+        let mut alice = old_impl::User {
+            address: ALICE,
+            balance: 100,
+        };
+        old.vote(&mut alice, 100);
+        old.append_rewards((0..3).map(|reward_id| (reward_id, 1000)).collect());
+
+        let mut users = HashMap::new();
+        users.insert(
+            ALICE,
+            User {
+                address: ALICE,
+                balance: 0,
+            },
+        );
+        let mut new = old_to_new(&old, PendingRewards::Pay, &mut users).unwrap();
+        let alice = users.get_mut(&ALICE).unwrap();
+        assert_eq!(new.try_unvote(alice), Err(DposError::StillLocked(5)));
+
+        new.append_reward(3, 1000);
+        new.append_reward(4, 1000);
+        assert_eq!(new.try_unvote(alice), Ok(()));
+        assert_eq!(alice.balance, 900 + 100 + 600);
+    }
+}
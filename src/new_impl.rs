@@ -1,25 +1,63 @@
+use super::add_dust;
+use super::audit::{AuditLog, AuditOp};
+use super::config::{ConfigError, ValidatorConfig};
+use super::traits::{DposError, Staking};
+use super::withdraw_fee;
 use super::Address;
 use super::Amount;
 use super::Index;
+use super::RewardIndex;
+use super::RoundingPolicy;
+use super::ValidatorState;
+use super::DUST_PRECISION;
+use super::MAX_WITHDRAW_FEE_BPS;
 use super::SHARE;
+use serde::{Deserialize, Serialize};
+use std::cmp;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
+// Fixed-point base of the reward per delegated token
+const REWARD_PER_UNIT_PRECISION: u128 = 1_000_000_000_000;
+
+#[derive(Serialize, Deserialize)]
 pub struct Vote {
     // The number of rewards that are already on the account at the time of voting
-    pub first_reward_id: Index,
+    pub first_reward_id: RewardIndex,
     // Vote amount
     pub amount: Amount,
-    // Indicates that the reward has been withdrawn for a given vote and it remains to close this vote
-    pub reward_taken: bool,
+    // The number of rewards that are already on the account at the time of the last claim
+    pub last_claimed_reward_id: RewardIndex,
+}
+
+// Where rewards settled for all users at once go
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Settlement {
+    // Rewards are accrued, users claim them
+    Accrue,
+    // Rewards are added to the votes, the part above the delegation cap is accrued
+    Compound,
 }
 
+// Amounts returned to the user on exit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExitSummary {
+    // Returned vote amount without the withdraw fee
+    pub principal: Amount,
+    // Final reward together with the accrued one
+    pub reward: Amount,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct User {
-    // User address
+    // User address, 0x prefixed hex when serialized
+    #[serde(with = "super::address")]
     pub address: Address,
     // User balance
     pub balance: Amount,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Validator {
     // Users votes by their addresses
     pub votes: HashMap<Address, Vote>,
@@ -28,38 +66,407 @@ pub struct Validator {
     // Total balance on that account (delegated + rewarded)
     pub total_balance: Amount,
     // Number of rewards for that validator
-    pub rewards_count: Index,
-    // The average reward value available for withdrawal by delegates.
-    // reward_for_user = (delegated_by_user / total_delegated) * (rewards_count - user_vote_time_rewards_count) * reward_to_share
-    pub reward_to_share: Amount,
+    pub rewards_count: RewardIndex,
+    // Sums of the users part of all rewards per delegated token before each reward index, every reward is divided by
+    // the total delegated at the time it is appended. The first one is zero (REWARD_PER_UNIT_PRECISION is 1.0).
+    // reward_for_user = delegated_by_user * (reward_per_unit[rewards_count] - reward_per_unit[last_claimed_reward_id])
+    pub reward_per_unit: Vec<Amount>,
+    // Minimal amount of a single vote (DEFAULT_MIN_VOTE by default)
+    pub min_vote: Amount,
+    // Maximum delegated balance on that account, unlimited if none
+    pub max_total_delegated: Option<Amount>,
+    // Maximum number of delegators, unlimited if none
+    pub max_delegators: Option<usize>,
+    // Indicates that votes and rewards are not accepted, withdrawals are still available
+    pub paused: bool,
+    // Minimal reward to send to a user, smaller rewards are accrued until they reach it
+    pub min_payout: Amount,
+    // Accrued rewards that have not been sent to users yet, they are still held on the account
+    pub accrued: HashMap<Address, Amount>,
+    // Users whose rewards are added to their votes instead of being sent
+    pub auto_compound: HashSet<Address>,
+    // Rounding of user rewards
+    pub rounding: RoundingPolicy,
+    // Accumulated rounding remainder of user rewards in DUST_PRECISION fractions of a token
+    pub dust: Amount,
+    // Lifecycle state, votes and rewards are accepted only by active validators
+    pub state: ValidatorState,
+    // External id of the last appended reward (block number or event id)
+    pub last_reward_id: Option<u64>,
+    // Number of rewards a vote stays locked after its first reward id
+    pub lockup_rewards: Index,
+    // Fee on withdrawn principal in basis points, up to MAX_WITHDRAW_FEE_BPS
+    pub withdraw_fee_bps: u32,
+    // Withdrawal fees that stay on the balance until the owner takes them
+    pub collected_fees: Amount,
+    // Log of committed operations
+    pub audit: AuditLog,
 }
 
-trait Democracy {
-    fn vote(&mut self, user: &mut User, amount: Amount);
-    fn unvote(&mut self, user: &mut User);
-}
+impl Validator {
+    // Empty validator with the given parameters
+    pub fn new(config: &ValidatorConfig) -> Result<Validator, ConfigError> {
+        config.validate()?;
 
-trait RewardSharing {
-    fn append_reward(&mut self, reward: Amount);
-    fn send_rewards(&mut self, user: &mut User);
-}
+        Ok(Validator {
+            votes: HashMap::new(),
+            total_delegated: 0,
+            total_balance: 0,
+            rewards_count: RewardIndex::default(),
+            reward_per_unit: vec![0],
+            min_vote: config.min_vote,
+            max_total_delegated: config.max_total_delegated,
+            max_delegators: config.max_delegators,
+            paused: false,
+            min_payout: config.min_payout,
+            accrued: HashMap::new(),
+            auto_compound: HashSet::new(),
+            rounding: config.rounding,
+            dust: 0,
+            state: ValidatorState::Active,
+            last_reward_id: None,
+            lockup_rewards: config.lockup_rewards,
+            withdraw_fee_bps: config.withdraw_fee_bps,
+            collected_fees: 0,
+            audit: AuditLog::new(config.audit_capacity),
+        })
+    }
+
+    // Serialized state of the validator
+    pub fn snapshot(&self) -> String {
+        serde_json::to_string(self).expect("Validator state is serializable")
+    }
+
+    // Validator restored from its snapshot
+    pub fn restore(snapshot: &str) -> serde_json::Result<Validator> {
+        serde_json::from_str(snapshot)
+    }
+
+    // Stop accepting votes and rewards for good, users can only withdraw
+    pub fn begin_deactivation(&mut self) {
+        if self.state != ValidatorState::Active {
+            panic!("Validator is not active");
+        }
+
+        self.state = ValidatorState::Deactivating;
+    }
+
+    // Send the rest of the balance to the owner once all users have withdrawn their support
+    pub fn finalize_deactivation(&mut self, owner: &mut User) -> Amount {
+        if self.state != ValidatorState::Deactivating {
+            panic!("Validator is not deactivating");
+        }
+
+        if !(self.votes.is_empty() && self.accrued.is_empty()) {
+            panic!("Users have not withdrawn everything yet");
+        }
+
+        // Send the rest: dust, fees and undistributed rewards
+        let rest = self.total_balance;
+        self.total_balance = 0;
+        self.collected_fees = 0;
+        owner.balance += rest;
+
+        self.state = ValidatorState::Closed;
+
+        rest
+    }
+
+    // Set the fee on withdrawn principal
+    pub fn set_withdraw_fee(&mut self, fee_bps: u32) {
+        if fee_bps > MAX_WITHDRAW_FEE_BPS {
+            panic!(
+                "Withdraw fee is above the maximum of {} bps",
+                MAX_WITHDRAW_FEE_BPS
+            );
+        }
+
+        self.withdraw_fee_bps = fee_bps;
+    }
+
+    // Send the collected withdrawal fees to the owner
+    pub fn withdraw_fees(&mut self, owner: &mut User) -> Amount {
+        let fees = self.collected_fees;
+        self.collected_fees = 0;
+
+        self.total_balance -= fees;
+        owner.balance += fees;
+
+        fees
+    }
+
+    // Send whole tokens of the accumulated dust to the owner, the fraction stays in the dust
+    pub fn sweep_dust(&mut self, owner: &mut User) -> Amount {
+        let swept = self.dust / DUST_PRECISION;
+        self.dust %= DUST_PRECISION;
+
+        self.total_balance -= swept;
+        owner.balance += swept;
+
+        swept
+    }
+
+    // Move the vote, accrued reward and auto compounding setting of one address to another one that has no vote.
+    // The destination takes the place of the source among the delegators, so the delegator limit can't be exceeded
+    pub fn transfer_vote(&mut self, from: Address, to: Address) {
+        // Check that vote exists
+        if !self.votes.contains_key(&from) {
+            panic!("No vote to transfer")
+        }
+
+        // Destination must not have a vote
+        if self.votes.contains_key(&to) {
+            panic!("Destination already has a vote");
+        }
+
+        let vote = self.votes.remove(&from).unwrap();
+        self.votes.insert(to, vote);
+
+        if let Some(accrued) = self.accrued.remove(&from) {
+            *self.accrued.entry(to).or_insert(0) += accrued;
+        }
+
+        if self.auto_compound.remove(&from) {
+            self.auto_compound.insert(to);
+        } else {
+            self.auto_compound.remove(&to);
+        }
+    }
+
+    // Indicates that the user has a vote
+    pub fn is_delegator(&self, address: Address) -> bool {
+        self.stake_of(address) > 0
+    }
+
+    // User vote amount
+    pub fn stake_of(&self, address: Address) -> Amount {
+        self.votes.get(&address).map_or(0, |vote| vote.amount)
+    }
+
+    // Users with the largest votes, with their part of the total delegated in basis points
+    pub fn top_delegators(&self, n: usize) -> Vec<(Address, Amount, u32)> {
+        let mut delegators: Vec<(Address, Amount, u32)> = self
+            .votes
+            .iter()
+            .filter(|(_, vote)| vote.amount > 0)
+            .map(|(address, vote)| {
+                let share_bps = (vote.amount * 10_000)
+                    .checked_div(self.total_delegated)
+                    .unwrap_or(0);
+                (*address, vote.amount, share_bps as u32)
+            })
+            .collect();
+
+        // Largest stakes first, equal stakes ordered by address
+        delegators.sort_unstable_by(|(address_a, amount_a, _), (address_b, amount_b, _)| {
+            amount_b.cmp(amount_a).then(address_a.cmp(address_b))
+        });
+        delegators.truncate(n);
+
+        delegators
+    }
+
+    // Settle the unclaimed rewards of all votes, the votes count rewards from now on.
+    // Returns settled rewards ordered by address
+    pub fn force_settle_all(&mut self, settlement: Settlement) -> Vec<(Address, Amount)> {
+        // Compute all rewards before any change
+        let mut settled: Vec<(Address, Amount)> = vec![];
+        for (address, vote) in self.votes.iter() {
+            if vote.amount == 0 || vote.last_claimed_reward_id == self.rewards_count {
+                continue;
+            }
+
+            let (reward, remainder) = self.rounding.divide(
+                vote.amount * self.reward_per_unit_since(vote.last_claimed_reward_id),
+                REWARD_PER_UNIT_PRECISION,
+            );
+            self.dust = add_dust(self.dust, remainder);
+            settled.push((*address, reward));
+        }
+        settled.sort_unstable();
+
+        for (address, reward) in settled.iter() {
+            let available = self.available_to_delegate();
+            let vote = self.votes.get_mut(address).unwrap();
+            vote.last_claimed_reward_id = self.rewards_count;
+
+            match settlement {
+                Settlement::Accrue if *reward > 0 => {
+                    *self.accrued.entry(*address).or_insert(0) += reward;
+                }
+                Settlement::Accrue => {}
+                Settlement::Compound => {
+                    let compounded = cmp::min(*reward, available);
+                    vote.amount += compounded;
+                    self.total_delegated += compounded;
+                    if *reward > compounded {
+                        *self.accrued.entry(*address).or_insert(0) += reward - compounded;
+                    }
+                }
+            }
+
+            self.audit.record(
+                AuditOp::SendRewards,
+                Some(*address),
+                *reward,
+                self.rewards_count.0,
+            );
+        }
+
+        settled
+    }
+
+    // Users part of the rewards per delegated token appended from the reward index on
+    fn reward_per_unit_since(&self, first_reward_id: RewardIndex) -> Amount {
+        if self.rewards_count.delta(first_reward_id) == 0 {
+            return 0;
+        }
+
+        let sum_at = |index: RewardIndex| {
+            self.reward_per_unit
+                .get(index.0 as usize)
+                .cloned()
+                .unwrap_or(0)
+        };
+        sum_at(self.rewards_count) - sum_at(first_reward_id)
+    }
+
+    // Stop accepting votes and rewards, withdrawals are still available
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    // Accept votes and rewards again
+    pub fn unpause(&mut self) {
+        self.paused = false;
+    }
+
+    // Enable or disable adding of user rewards to the user vote
+    pub fn set_auto_compound(&mut self, user: &User, enabled: bool) {
+        // Check that validator is not paused
+        if self.paused {
+            panic!("Validator is paused");
+        }
+
+        if enabled {
+            self.auto_compound.insert(user.address);
+        } else {
+            self.auto_compound.remove(&user.address);
+        }
+    }
+
+    // Claim the pending and accrued rewards and unvote in one step. Nothing changes if the vote can't be closed
+    pub fn exit(&mut self, user: &mut User) -> Result<ExitSummary, DposError> {
+        let amount = self.stake_of(user.address);
+        let balance = user.balance;
+
+        self.try_unvote(user)?;
+
+        // The reward below the minimal payout is not left behind
+        if self.accrued.contains_key(&user.address) {
+            self.claim_accrued(user);
+        }
+
+        let principal = amount - withdraw_fee(amount, self.withdraw_fee_bps);
+        Ok(ExitSummary {
+            principal,
+            reward: user.balance - balance - principal,
+        })
+    }
+
+    // Check that a new delegator fits into the limit, existing ones can always vote
+    fn check_delegator(&self, address: Address) -> Result<(), DposError> {
+        if let Some(max_delegators) = self.max_delegators {
+            if !self.votes.contains_key(&address) && self.votes.len() >= max_delegators {
+                return Err(DposError::DelegatorLimitReached(max_delegators));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check that validator accepts votes and the amount can be delegated
+    fn check_vote_amount(&self, user: &User, amount: Amount) -> Result<(), DposError> {
+        // Check that validator accepts votes
+        if self.paused {
+            return Err(DposError::Paused);
+        }
+
+        // Check that validator is active
+        if self.state != ValidatorState::Active {
+            return Err(DposError::NotActive);
+        }
+
+        // Reject zero and dust votes
+        if amount < self.min_vote {
+            return Err(DposError::BelowMinVote(self.min_vote));
+        }
 
-impl Democracy for Validator {
-    fn vote(&mut self, user: &mut User, amount: Amount) {
-        // First check that user has no votes (her previous vote and reward for it has been withdrawn)
+        // User must have the amount
+        if amount > user.balance {
+            return Err(DposError::InsufficientBalance(user.balance));
+        }
+
+        // Check that the new delegated balance fits into the cap
+        let available = self.available_to_delegate();
+        if amount > available {
+            return Err(DposError::CapExceeded(available));
+        }
+
+        Ok(())
+    }
+
+    // Amount the delegated balance can still grow by under the delegation cap, unlimited without the cap
+    fn available_to_delegate(&self) -> Amount {
+        match self.max_total_delegated {
+            Some(max_total_delegated) => max_total_delegated.saturating_sub(self.total_delegated),
+            None => Amount::MAX,
+        }
+    }
+
+    // Check that validator accepts the reward
+    fn check_reward(&self, reward_id: u64) -> Result<(), DposError> {
+        // Check that validator is not paused
+        if self.paused {
+            return Err(DposError::Paused);
+        }
+
+        // Check that validator is active
+        if self.state != ValidatorState::Active {
+            return Err(DposError::NotActive);
+        }
+
+        // Reject rewards that have already been appended
+        if self.last_reward_id.is_some_and(|last| reward_id <= last) {
+            return Err(DposError::DuplicateReward(reward_id));
+        }
+
+        Ok(())
+    }
+
+    // Delegate the amount, the vote earns the rewards appended from now on
+    pub fn vote(&mut self, user: &mut User, amount: Amount) {
+        // First check that user has no votes (her previous vote has been withdrawn)
         if let Some(prev_vote) = self.votes.get(&user.address) {
-            if prev_vote.amount > 0 || !prev_vote.reward_taken {
-                panic!("Get reward and unvote before revoting");
+            if prev_vote.amount > 0 {
+                panic!("Unvote before revoting");
             }
         }
 
+        if let Err(error) = self.check_vote_amount(user, amount) {
+            panic!("{}", error);
+        }
+        if let Err(error) = self.check_delegator(user.address) {
+            panic!("{}", error);
+        }
+
         // Insert new vote
         self.votes.insert(
             user.address,
             Vote {
                 first_reward_id: self.rewards_count,
                 amount,
-                reward_taken: false,
+                last_claimed_reward_id: self.rewards_count,
             },
         );
 
@@ -67,9 +474,65 @@ impl Democracy for Validator {
         user.balance -= amount;
         self.total_delegated += amount;
         self.total_balance += amount;
+
+        self.audit.record(
+            AuditOp::Vote,
+            Some(user.address),
+            amount,
+            self.rewards_count.0,
+        );
     }
 
-    fn unvote(&mut self, user: &mut User) {
+    // Adding stake settles the vote first: the pending reward is sent (or compounded) and the rewards are counted
+    // from now on for the whole new amount. So the added stake doesn't earn for the past rewards and
+    // doesn't dilute the reward already earned by the vote
+    pub fn add_stake(&mut self, user: &mut User, amount: Amount) {
+        // Check that vote exists
+        let vote = self.votes.get(&user.address);
+        if vote.is_none() {
+            panic!("No vote to add stake to")
+        }
+
+        let vote = vote.unwrap();
+
+        // Vote amount must not be zero
+        if vote.amount == 0 {
+            panic!("Make sure that the vote exists");
+        }
+
+        if let Err(error) = self.check_vote_amount(user, amount) {
+            panic!("{}", error);
+        }
+
+        // Settle the pending reward
+        self.send_rewards(user);
+
+        // Update vote: new amount counts rewards from now on
+        let vote_amount = self.votes.get(&user.address).unwrap().amount;
+        self.votes.insert(
+            user.address,
+            Vote {
+                first_reward_id: self.rewards_count,
+                amount: vote_amount + amount,
+                last_claimed_reward_id: self.rewards_count,
+            },
+        );
+
+        // Update balances: user, delegated, total
+        user.balance -= amount;
+        self.total_delegated += amount;
+        self.total_balance += amount;
+
+        self.audit.record(
+            AuditOp::AddStake,
+            Some(user.address),
+            amount,
+            self.rewards_count.0,
+        );
+    }
+
+    // Return the vote amount, all rewards of the vote must be claimed
+    pub fn unvote(&mut self, user: &mut User) {
         // Check that vote exists
         let vote = self.votes.get(&user.address);
         if vote.is_none() {
@@ -78,35 +541,67 @@ impl Democracy for Validator {
 
         let vote = vote.unwrap();
 
-        // Vote amount must not be zero and its reward must be withdrawn
-        if vote.amount == 0 || !vote.reward_taken {
-            panic!("Make sure that the vote exists and the reward has been withdrawn");
+        // Vote amount must not be zero and all its rewards must be claimed
+        if vote.amount == 0 || vote.last_claimed_reward_id != self.rewards_count {
+            panic!("Make sure that the vote exists and the reward has been claimed");
+        }
+
+        // Check that the vote is not locked
+        let unlocks_at = vote.first_reward_id.after(self.lockup_rewards);
+        if self.rewards_count < unlocks_at {
+            panic!("Vote is locked until {} rewards", unlocks_at.0);
         }
 
-        // Update balances: user, delegated and total
-        user.balance += vote.amount;
+        // Total delegated must not go below zero
+        if vote.amount > self.total_delegated {
+            panic!("{}", DposError::NoDelegation);
+        }
+
+        // Update balances: user, delegated and total. The fee is taken from the principal and stays on the balance
+        let fee = withdraw_fee(vote.amount, self.withdraw_fee_bps);
+        let payout = vote.amount - fee;
+        user.balance += payout;
         self.total_delegated -= vote.amount;
-        self.total_balance -= vote.amount;
+        self.total_balance -= payout;
+        self.collected_fees += fee;
 
         // Delete vote
         self.votes.remove(&user.address);
+
+        self.audit.record(
+            AuditOp::Unvote,
+            Some(user.address),
+            payout,
+            self.rewards_count.0,
+        );
     }
-}
 
-impl RewardSharing for Validator {
-    fn append_reward(&mut self, reward: Amount) {
+    // Share users part of the reward with the votes
+    pub fn append_reward(&mut self, reward_id: u64, reward: Amount) {
+        if let Err(error) = self.check_reward(reward_id) {
+            panic!("{}", error);
+        }
+        self.last_reward_id = Some(reward_id);
+
         // Update total balance
         self.total_balance += reward;
 
         // Update passed rewards count
-        self.rewards_count += 1;
+        self.rewards_count = self.rewards_count.succ();
+
+        // Share users part of the reward per delegated token, nothing is shared if nobody has delegated
+        let reward_per_unit = self.reward_per_unit.last().cloned().unwrap_or(0)
+            + (reward * SHARE * REWARD_PER_UNIT_PRECISION)
+                .checked_div(100 * self.total_delegated)
+                .unwrap_or(0);
+        self.reward_per_unit.push(reward_per_unit);
 
-        // Calculate new value for a reward to share with users
-        let medium = (self.reward_to_share + reward) / 2;
-        self.reward_to_share = SHARE * medium / 100;
+        self.audit
+            .record(AuditOp::Reward, None, reward, self.rewards_count.0);
     }
 
-    fn send_rewards(&mut self, user: &mut User) {
+    // Send (or compound) the rewards appended since the last claim
+    pub fn send_rewards(&mut self, user: &mut User) {
         // Check that vote exists
         let vote = self.votes.get(&user.address);
         if vote.is_none() {
@@ -115,30 +610,876 @@ impl RewardSharing for Validator {
 
         let vote = vote.unwrap();
 
-        // Vote amount must not be zero (it must not be withdrawn) and reward has not been taken
+        // Vote amount must not be zero (it must not be withdrawn)
         let amount = vote.amount;
-        if amount == 0 || vote.reward_taken {
-            panic!("Make sure that the vote exists and the reward has not been withdrawn. If reward has been withdrawn - unvote.");
+        if amount == 0 {
+            panic!("Make sure that the vote exists");
+        }
+
+        // Total delegated includes the vote, so it can't be zero here unless the state is inconsistent
+        if amount > self.total_delegated {
+            panic!("{}", DposError::NoDelegation);
         }
 
-        // Calculate rewards count that passed since user vote
+        // Calculate reward: user part of the rewards appended since the last claim
         let first_reward_id = vote.first_reward_id;
-        let rewards_passed = (self.rewards_count - first_reward_id) as u128;
-        // Calculate reward
-        let reward = (vote.amount / self.total_delegated) * rewards_passed * self.reward_to_share;
+        let (reward, remainder) = self.rounding.divide(
+            vote.amount * self.reward_per_unit_since(vote.last_claimed_reward_id),
+            REWARD_PER_UNIT_PRECISION,
+        );
+        self.dust = add_dust(self.dust, remainder);
 
-        // Update user and total balances
-        user.balance += reward;
-        self.total_balance -= reward;
+        // If user compounds rewards - add the part of the reward that fits under the delegation cap to the vote
+        // amount, the rest is sent
+        let compounded = if self.auto_compound.contains(&user.address) {
+            cmp::min(reward, self.available_to_delegate())
+        } else {
+            0
+        };
+        self.total_delegated += compounded;
+
+        // Add the reward to the accrued one and send it only if it reaches the minimal payout
+        let reward = reward - compounded + self.accrued.remove(&user.address).unwrap_or(0);
+        if reward > 0 && reward < self.min_payout {
+            self.accrued.insert(user.address, reward);
+        } else {
+            // Update user and total balances
+            user.balance += reward;
+            self.total_balance -= reward;
+        }
 
-        // Update vote - reward has been taken
+        // Update vote - rewards have been claimed up to now
         self.votes.insert(
             user.address,
             Vote {
                 first_reward_id,
-                amount,
-                reward_taken: true,
+                amount: amount + compounded,
+                last_claimed_reward_id: self.rewards_count,
             },
         );
+
+        self.audit.record(
+            AuditOp::SendRewards,
+            Some(user.address),
+            compounded + reward,
+            self.rewards_count.0,
+        );
+    }
+
+    // Send the rewards accrued below the minimal payout
+    pub fn claim_accrued(&mut self, user: &mut User) {
+        // Check that there is accrued reward
+        let reward = self.accrued.remove(&user.address);
+        if reward.is_none() {
+            panic!("No accrued reward to claim")
+        }
+
+        let reward = reward.unwrap();
+
+        // Update user and total balances
+        user.balance += reward;
+        self.total_balance -= reward;
+
+        self.audit.record(
+            AuditOp::ClaimAccrued,
+            Some(user.address),
+            reward,
+            self.rewards_count.0,
+        );
+    }
+}
+
+impl Staking for Validator {
+    type User = User;
+
+    fn try_vote(&mut self, user: &mut User, amount: Amount) -> Result<(), DposError> {
+        if let Some(prev_vote) = self.votes.get(&user.address) {
+            if prev_vote.amount > 0 {
+                return Err(DposError::AlreadyVoted);
+            }
+        }
+        self.check_vote_amount(user, amount)?;
+        self.check_delegator(user.address)?;
+        self.vote(user, amount);
+
+        Ok(())
+    }
+
+    // Send the pending reward if it has not been claimed and unvote
+    fn try_unvote(&mut self, user: &mut User) -> Result<(), DposError> {
+        let vote = self.votes.get(&user.address).ok_or(DposError::NoVote)?;
+        if vote.amount == 0 {
+            return Err(DposError::NoVote);
+        }
+
+        let unlocks_at = vote.first_reward_id.after(self.lockup_rewards);
+        if self.rewards_count < unlocks_at {
+            return Err(DposError::StillLocked(unlocks_at.0));
+        }
+        if vote.amount > self.total_delegated {
+            return Err(DposError::NoDelegation);
+        }
+
+        // The last reward is sent even if the user compounds rewards
+        if vote.last_claimed_reward_id != self.rewards_count {
+            let compounding = self.auto_compound.remove(&user.address);
+            self.send_rewards(user);
+            if compounding {
+                self.auto_compound.insert(user.address);
+            }
+        }
+        self.unvote(user);
+
+        Ok(())
+    }
+
+    fn try_append_reward(&mut self, reward_id: u64, reward: Amount) -> Result<(), DposError> {
+        self.check_reward(reward_id)?;
+        self.append_reward(reward_id, reward);
+
+        Ok(())
+    }
+
+    fn try_claim_rewards(&mut self, user: &mut User) -> Result<(), DposError> {
+        let vote = self.votes.get(&user.address).ok_or(DposError::NoVote)?;
+        if vote.amount == 0 {
+            return Err(DposError::NoVote);
+        }
+        if vote.amount > self.total_delegated {
+            return Err(DposError::NoDelegation);
+        }
+        self.send_rewards(user);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(config: ValidatorConfig) -> Validator {
+        Validator::new(&config).unwrap()
+    }
+
+    fn user(address: Address, balance: Amount) -> User {
+        User { address, balance }
+    }
+
+    #[test]
+    fn rewards_of_large_amounts_do_not_overflow() {
+        let mut validator = validator(ValidatorConfig::default());
+        let amount = 10u128.pow(18);
+        let mut alice = user(1, amount);
+
+        validator.vote(&mut alice, amount);
+        validator.append_reward(0, amount);
+        validator.send_rewards(&mut alice);
+
+        assert_eq!(alice.balance, amount * SHARE / 100);
+    }
+
+    // Rewards of alice and bob voting 100 and 300 before the rewards and claiming after them
+    fn payouts(rewards: &[Amount]) -> (Amount, Amount) {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 300);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 300);
+        for (reward_id, reward) in rewards.iter().enumerate() {
+            validator.append_reward(reward_id as u64, *reward);
+        }
+        validator.send_rewards(&mut alice);
+        validator.send_rewards(&mut bob);
+
+        (alice.balance, bob.balance)
+    }
+
+    #[test]
+    fn payouts_do_not_depend_on_reward_order() {
+        assert_eq!(payouts(&[100, 10_000]), payouts(&[10_000, 100]));
+        assert_eq!(payouts(&[100, 10_000]), (757, 2272));
+    }
+
+    #[test]
+    fn claims_after_an_unvote_are_not_overpaid() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.append_reward(0, 1000);
+
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        validator.send_rewards(&mut bob);
+        assert_eq!(alice.balance, 100 + 150);
+        assert_eq!(bob.balance, 150);
+    }
+
+    #[test]
+    fn later_votes_do_not_dilute_earlier_rewards() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut bob, 100);
+
+        validator.send_rewards(&mut alice);
+        validator.send_rewards(&mut bob);
+        assert_eq!(alice.balance, 300);
+        assert_eq!(bob.balance, 0);
+    }
+
+    // Balances of alice voting 100 and bob voting 300 after five rewards, alice claims after the given ones
+    fn claims_after(claims: &[usize]) -> (Amount, Amount) {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 300);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 300);
+        for reward_id in 0..5 {
+            validator.append_reward(reward_id, 1000 + 200 * reward_id as Amount);
+            if claims.contains(&(reward_id as usize + 1)) {
+                validator.send_rewards(&mut alice);
+            }
+        }
+
+        (alice.balance, validator.stake_of(1))
+    }
+
+    #[test]
+    fn repeated_claims_sum_to_one_claim() {
+        assert_eq!(claims_after(&[3, 5]), claims_after(&[5]));
+        assert_eq!(claims_after(&[5]), (3 * 7000 / 4 / 10, 100));
+
+        // The first claim pays the first three rewards
+        assert_eq!(claims_after(&[3]).0, 3 * 3600 / 4 / 10);
+    }
+
+    #[test]
+    fn unvote_requires_all_rewards_claimed() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.send_rewards(&mut alice);
+        validator.append_reward(1, 1000);
+        assert_eq!(validator.try_claim_rewards(&mut alice), Ok(()));
+
+        validator.unvote(&mut alice);
+        assert_eq!(alice.balance, 100 + 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "Make sure that the vote exists and the reward has been claimed")]
+    fn unvote_with_unclaimed_rewards_panics() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.send_rewards(&mut alice);
+        validator.append_reward(1, 1000);
+        validator.unvote(&mut alice);
+    }
+
+    // Validator with alice voting 1000 and bob 3000 and two rewards appended
+    fn exit_validator(config: ValidatorConfig) -> (Validator, User) {
+        let mut validator = validator(config);
+        let mut alice = user(1, 1000);
+
+        validator.vote(&mut alice, 1000);
+        validator.vote(&mut user(2, 3000), 3000);
+        validator.append_reward(0, 1000);
+        validator.append_reward(1, 2000);
+
+        (validator, alice)
+    }
+
+    #[test]
+    fn exit_matches_claim_and_unvote() {
+        let config = ValidatorConfig::builder()
+            .withdraw_fee_bps(100)
+            .build()
+            .unwrap();
+
+        let (mut validator, mut alice) = exit_validator(config.clone());
+        let summary = validator.exit(&mut alice).unwrap();
+
+        let (mut two_step, mut two_step_alice) = exit_validator(config);
+        two_step.send_rewards(&mut two_step_alice);
+        let reward = two_step_alice.balance;
+        two_step.unvote(&mut two_step_alice);
+
+        assert_eq!(summary.reward, reward);
+        assert_eq!(summary.principal, two_step_alice.balance - reward);
+        assert_eq!(
+            summary,
+            ExitSummary {
+                principal: 990,
+                reward: 225
+            }
+        );
+        assert_eq!(alice.balance, two_step_alice.balance);
+        assert_eq!(validator.total_balance, two_step.total_balance);
+        assert!(!validator.votes.contains_key(&1));
+    }
+
+    #[test]
+    fn exit_after_a_claim() {
+        let (mut validator, mut alice) = exit_validator(ValidatorConfig::default());
+
+        validator.send_rewards(&mut alice);
+        let summary = validator.exit(&mut alice).unwrap();
+        assert_eq!(
+            summary,
+            ExitSummary {
+                principal: 1000,
+                reward: 0
+            }
+        );
+        assert_eq!(alice.balance, 1000 + 225);
+    }
+
+    #[test]
+    fn exit_pays_the_accrued_reward() {
+        let config = ValidatorConfig::builder().min_payout(1000).build().unwrap();
+        let (mut validator, mut alice) = exit_validator(config);
+
+        validator.send_rewards(&mut alice);
+        assert_eq!(alice.balance, 0);
+
+        let summary = validator.exit(&mut alice).unwrap();
+        assert_eq!(summary.reward, 225);
+        assert_eq!(alice.balance, 1000 + 225);
+        assert!(validator.accrued.is_empty());
+    }
+
+    #[test]
+    fn exit_of_a_locked_vote_changes_nothing() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(5)
+            .build()
+            .unwrap();
+        let (mut validator, mut alice) = exit_validator(config);
+
+        assert_eq!(validator.exit(&mut alice), Err(DposError::StillLocked(5)));
+        assert_eq!(alice.balance, 0);
+        assert_eq!(validator.stake_of(1), 1000);
+    }
+
+    #[test]
+    fn vote_and_reward_are_audited_in_order() {
+        let mut validator = validator(ValidatorConfig::default());
+
+        validator.vote(&mut user(1, 100), 100);
+        validator.append_reward(0, 1000);
+
+        let entries = validator.audit.since(0);
+        let logged: Vec<(u64, AuditOp, Option<Address>, Amount, Index)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.seq,
+                    entry.op,
+                    entry.address,
+                    entry.amount,
+                    entry.index,
+                )
+            })
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                (0, AuditOp::Vote, Some(1), 100, 0),
+                (1, AuditOp::Reward, None, 1000, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn sub_threshold_rewards_are_paid_in_one_transfer() {
+        let config = ValidatorConfig::builder().min_payout(100).build().unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+
+        // Every reward gives alice 30, below the minimal payout
+        for reward_id in 0..3 {
+            validator.append_reward(reward_id, 100);
+            validator.send_rewards(&mut alice);
+            assert_eq!(alice.balance, 0);
+            assert_eq!(validator.accrued[&1], 30 * (reward_id as Amount + 1));
+        }
+        // Accrued rewards are still held by the validator
+        assert_eq!(validator.total_balance, 100 + 300);
+
+        validator.append_reward(3, 100);
+        validator.send_rewards(&mut alice);
+        assert_eq!(alice.balance, 120);
+        assert!(validator.accrued.is_empty());
+        assert_eq!(validator.total_balance, 100 + 400 - 120);
+    }
+
+    #[test]
+    fn zero_reward_is_not_accrued() {
+        let config = ValidatorConfig::builder().min_payout(100).build().unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 0);
+        validator.begin_deactivation();
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert!(validator.accrued.is_empty());
+
+        assert_eq!(validator.finalize_deactivation(&mut user(2, 0)), 0);
+        assert_eq!(alice.balance, 100);
+    }
+
+    #[test]
+    fn compounding_user_is_paid_out_on_unvote() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.set_auto_compound(&alice, true);
+        validator.append_reward(0, 1000);
+
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert_eq!(alice.balance, 100 + 300);
+        assert!(!validator.is_delegator(1));
+    }
+
+    #[test]
+    fn compounding_respects_the_delegation_cap() {
+        let config = ValidatorConfig::builder()
+            .max_total_delegated(250)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.set_auto_compound(&alice, true);
+
+        // Only 150 of the reward of 300 fits under the cap, the rest is sent
+        validator.append_reward(0, 1000);
+        validator.send_rewards(&mut alice);
+        assert_eq!(validator.stake_of(1), 250);
+        assert_eq!(validator.total_delegated, 250);
+        assert_eq!(alice.balance, 150);
+
+        // The cap is reached, the whole reward is sent
+        validator.append_reward(1, 1000);
+        validator.send_rewards(&mut alice);
+        assert_eq!(validator.stake_of(1), 250);
+        assert_eq!(alice.balance, 150 + 300);
+    }
+
+    #[test]
+    fn compounding_settlement_accrues_the_part_above_the_cap() {
+        let config = ValidatorConfig::builder()
+            .max_total_delegated(250)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        assert_eq!(
+            validator.force_settle_all(Settlement::Compound),
+            vec![(1, 300)]
+        );
+        assert_eq!(validator.stake_of(1), 250);
+        assert_eq!(validator.total_delegated, 250);
+        assert_eq!(validator.accrued[&1], 150);
+    }
+
+    #[test]
+    fn compounding_delegator_ends_with_more_stake() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.set_auto_compound(&alice, true);
+
+        for reward_id in 0..5 {
+            validator.append_reward(reward_id, 1000);
+            validator.send_rewards(&mut alice);
+            validator.send_rewards(&mut bob);
+        }
+
+        assert_eq!(alice.balance, 0);
+        assert!(bob.balance > 0);
+        assert_eq!(validator.stake_of(2), 100);
+        assert!(validator.stake_of(1) > 100 + bob.balance);
+    }
+
+    #[test]
+    fn top_delegators_with_their_share() {
+        let mut validator = validator(ValidatorConfig::default());
+
+        validator.vote(&mut user(1, 1), 1);
+        validator.vote(&mut user(2, 2), 2);
+        validator.vote(&mut user(4, 1), 1);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut user(3, 2), 2);
+
+        assert_eq!(
+            validator.top_delegators(3),
+            vec![(2, 2, 3333), (3, 2, 3333), (1, 1, 1666)]
+        );
+        assert_eq!(validator.top_delegators(10).len(), 4);
+        assert_eq!(validator.top_delegators(10)[3], (4, 1, 1666));
+    }
+
+    #[test]
+    fn transferred_vote_withdraws_what_the_source_could() {
+        let config = ValidatorConfig::builder().min_payout(100).build().unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut user(2, 100), 100);
+        validator.append_reward(0, 500);
+        validator.send_rewards(&mut alice);
+        validator.append_reward(1, 1000);
+
+        let mut expected = user(1, 0);
+        Validator::restore(&validator.snapshot())
+            .unwrap()
+            .try_unvote(&mut expected)
+            .unwrap();
+
+        validator.transfer_vote(1, 3);
+        let mut carol = user(3, 0);
+        assert_eq!(validator.try_unvote(&mut carol), Ok(()));
+        assert_eq!(carol.balance, expected.balance);
+        assert_eq!(carol.balance, 100 + 75 + 150);
+
+        assert!(!validator.is_delegator(1));
+        assert!(!validator.accrued.contains_key(&1));
+        assert_eq!(validator.try_unvote(&mut alice), Err(DposError::NoVote));
+    }
+
+    #[test]
+    fn transfers_keep_the_delegator_limit_and_compounding() {
+        let config = ValidatorConfig::builder()
+            .max_delegators(2)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut user(2, 100), 100);
+        validator.set_auto_compound(&alice, true);
+
+        validator.transfer_vote(1, 3);
+        assert_eq!(validator.votes.len(), 2);
+        assert!(validator.auto_compound.contains(&3));
+        assert!(!validator.auto_compound.contains(&1));
+        assert_eq!(
+            validator.try_vote(&mut user(4, 100), 100),
+            Err(DposError::DelegatorLimitReached(2))
+        );
+    }
+
+    #[test]
+    fn votes_are_blocked_while_paused() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 300);
+
+        validator.pause();
+        assert_eq!(validator.try_vote(&mut alice, 100), Err(DposError::Paused));
+
+        validator.unpause();
+        assert_eq!(validator.try_vote(&mut alice, 100), Ok(()));
+
+        validator.pause();
+        assert_eq!(
+            validator.try_vote(&mut user(2, 100), 100),
+            Err(DposError::Paused)
+        );
+        assert_eq!(alice.balance, 200);
+
+        validator.unpause();
+        assert_eq!(validator.try_vote(&mut user(2, 100), 100), Ok(()));
+        assert_eq!(validator.total_delegated, 200);
+    }
+
+    #[test]
+    fn bankers_rounding_does_not_leak_small_rewards() {
+        // Alice's part of the rewards alternates between 2.5 and 1.5 tokens
+        let claimed = |rounding: RoundingPolicy| {
+            let mut validator = validator(
+                ValidatorConfig::builder()
+                    .rounding(rounding)
+                    .build()
+                    .unwrap(),
+            );
+            let mut alice = user(1, 1000);
+            let mut bob = user(2, 1000);
+            validator.vote(&mut alice, 100);
+            validator.vote(&mut bob, 200);
+
+            for reward_id in 0..100 {
+                validator.append_reward(reward_id, if reward_id % 2 == 0 { 25 } else { 15 });
+                validator.send_rewards(&mut alice);
+            }
+
+            (alice.balance - 900, validator.dust)
+        };
+
+        // Floor loses half a token on every reward to the dust, banker's rounding evens out at the exact 200
+        assert_eq!(claimed(RoundingPolicy::Floor), (150, 50 * DUST_PRECISION));
+        assert_eq!(claimed(RoundingPolicy::Bankers), (200, 0));
+    }
+
+    #[test]
+    fn replayed_reward_is_appended_once() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 100), 100);
+
+        assert_eq!(validator.try_append_reward(7, 1000), Ok(()));
+        assert_eq!(
+            validator.try_append_reward(7, 1000),
+            Err(DposError::DuplicateReward(7))
+        );
+        assert_eq!(
+            validator.try_append_reward(3, 1000),
+            Err(DposError::DuplicateReward(3))
+        );
+
+        assert_eq!(validator.rewards_count, RewardIndex(1));
+        assert_eq!(validator.total_balance, 100 + 1000);
+    }
+
+    #[test]
+    fn dust_of_100_rewards_is_swept_to_the_owner() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 200);
+        let mut owner = user(3, 0);
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 200);
+
+        // Alice's part of every reward is 1.3 tokens, 0.3 of it is rounded down into the dust
+        for reward_id in 0..100 {
+            validator.append_reward(reward_id, 13);
+            validator.send_rewards(&mut alice);
+        }
+        assert_eq!(alice.balance, 100);
+        assert_eq!(validator.dust, 30 * DUST_PRECISION);
+
+        assert_eq!(validator.sweep_dust(&mut owner), 30);
+        assert_eq!(validator.dust, 0);
+        assert_eq!(validator.sweep_dust(&mut owner), 0);
+
+        // Swept tokens are not paid twice
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert_eq!(validator.try_unvote(&mut bob), Ok(()));
+        assert_eq!(bob.balance, 200 + 260);
+        assert_eq!(
+            alice.balance + bob.balance + owner.balance + validator.total_balance,
+            300 + 1300
+        );
+    }
+
+    #[test]
+    fn add_stake_settles_the_pending_reward_first() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 200);
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        // The reward of the old amount is paid before the stake grows
+        validator.add_stake(&mut alice, 100);
+        assert_eq!(alice.balance, 300);
+        assert_eq!(validator.stake_of(1), 200);
+        assert_eq!(validator.votes[&1].first_reward_id, RewardIndex(1));
+
+        // The added stake earns nothing of the rewards appended before it
+        validator.send_rewards(&mut alice);
+        assert_eq!(alice.balance, 300);
+    }
+
+    #[test]
+    fn added_stake_dilutes_only_later_rewards() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 400);
+        let mut bob = user(2, 100);
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.append_reward(0, 1000);
+
+        validator.add_stake(&mut alice, 200);
+        assert_eq!(alice.balance, 400 - 300 + 150);
+        validator.append_reward(1, 1000);
+
+        // Bob keeps half of the first reward and gets a quarter of the second one
+        validator.send_rewards(&mut alice);
+        validator.send_rewards(&mut bob);
+        assert_eq!(alice.balance, 400 - 300 + 150 + 225);
+        assert_eq!(bob.balance, 150 + 75);
+    }
+
+    #[test]
+    fn restored_snapshot_continues_like_the_original() {
+        let config = ValidatorConfig::builder().min_payout(100).build().unwrap();
+        let mut original = validator(config);
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 200);
+        original.vote(&mut alice, 100);
+        original.vote(&mut bob, 200);
+        original.set_auto_compound(&bob, true);
+        original.append_reward(0, 13);
+        original.send_rewards(&mut alice);
+
+        let mut restored = Validator::restore(&original.snapshot()).unwrap();
+        assert_eq!(restored.total_delegated, original.total_delegated);
+        assert_eq!(restored.total_balance, original.total_balance);
+        assert_eq!(restored.rewards_count, original.rewards_count);
+        assert_eq!(restored.reward_per_unit, original.reward_per_unit);
+        assert_eq!(restored.accrued, HashMap::from([(1, 1)]));
+        assert_eq!(restored.auto_compound, original.auto_compound);
+        assert_eq!(restored.dust, 300_000);
+        assert_eq!(restored.last_reward_id, Some(0));
+        assert_eq!(restored.audit.entries, original.audit.entries);
+
+        // Both copies pay the same afterwards
+        let balances = |validator: &mut Validator| {
+            let mut alice = user(1, alice.balance);
+            let mut bob = user(2, bob.balance);
+            validator.append_reward(1, 1000);
+            validator.try_unvote(&mut alice).unwrap();
+            validator.try_unvote(&mut bob).unwrap();
+            (alice.balance, bob.balance, validator.total_balance)
+        };
+        assert_eq!(balances(&mut restored), balances(&mut original));
+
+        assert!(Validator::restore("{}").is_err());
+    }
+
+    #[test]
+    fn payout_and_fee_add_up_to_the_principal() {
+        for amount in [101, 333, 999, 1001, 12_345] {
+            let config = ValidatorConfig::builder()
+                .withdraw_fee_bps(250)
+                .build()
+                .unwrap();
+            let mut validator = validator(config);
+            let mut alice = user(1, amount);
+
+            validator.vote(&mut alice, amount);
+            validator.unvote(&mut alice);
+
+            // The fee is rounded down and stays on the balance
+            assert_eq!(validator.collected_fees, amount * 250 / 10_000);
+            assert_eq!(alice.balance + validator.collected_fees, amount);
+            assert_eq!(validator.total_balance, validator.collected_fees);
+        }
+    }
+
+    #[test]
+    fn delegator_limit_rejects_only_new_addresses() {
+        let config = ValidatorConfig::builder()
+            .max_delegators(3)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 300);
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut user(2, 100), 100);
+        validator.vote(&mut user(3, 100), 100);
+
+        assert_eq!(
+            validator.try_vote(&mut user(4, 100), 100),
+            Err(DposError::DelegatorLimitReached(3))
+        );
+
+        // An existing delegator still adds stake
+        validator.add_stake(&mut alice, 100);
+        assert_eq!(validator.stake_of(1), 200);
+        assert_eq!(validator.votes.len(), 3);
+
+        // A place frees up once a delegator leaves
+        validator.unvote(&mut user(2, 0));
+        assert_eq!(validator.try_vote(&mut user(4, 100), 100), Ok(()));
+    }
+
+    #[test]
+    fn force_settle_all_settles_three_delegators() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut carol = user(3, 300);
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut user(2, 200), 200);
+        validator.vote(&mut carol, 300);
+
+        // Carol claims the first reward herself
+        validator.append_reward(0, 1000);
+        validator.send_rewards(&mut carol);
+        validator.append_reward(1, 2000);
+
+        assert_eq!(
+            validator.force_settle_all(Settlement::Accrue),
+            vec![(1, 50 + 100), (2, 100 + 200), (3, 300)]
+        );
+        assert_eq!(
+            validator.accrued,
+            HashMap::from([(1, 150), (2, 300), (3, 300)])
+        );
+        assert!(validator.force_settle_all(Settlement::Accrue).is_empty());
+
+        // Settled rewards are paid once
+        validator.claim_accrued(&mut alice);
+        validator.send_rewards(&mut alice);
+        assert_eq!(alice.balance, 150);
+    }
+
+    #[test]
+    fn corrupt_total_delegated_is_rejected() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        // Total delegated doesn't cover the vote, nothing is paid
+        validator.total_delegated = 0;
+        assert_eq!(
+            validator.try_claim_rewards(&mut alice),
+            Err(DposError::NoDelegation)
+        );
+        assert_eq!(
+            validator.try_unvote(&mut alice),
+            Err(DposError::NoDelegation)
+        );
+        assert_eq!(alice.balance, 0);
+        assert_eq!(validator.total_balance, 1100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Total delegated doesn't cover the vote")]
+    fn send_rewards_panics_on_corrupt_total_delegated() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        validator.total_delegated = 0;
+        validator.send_rewards(&mut alice);
     }
 }
@@ -1,24 +1,58 @@
 use std::cmp;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
+use serde::{Deserialize, Serialize};
+
+use super::add_dust;
+use super::audit::{AuditLog, AuditOp};
+use super::config::{ConfigError, ValidatorConfig};
+use super::traits::{DposError, Staking};
+use super::unix_time;
+use super::withdraw_fee;
 use super::Address;
 use super::Amount;
 use super::Index;
+use super::RewardIndex;
+use super::RoundingPolicy;
+use super::ValidatorState;
+use super::DUST_PRECISION;
+use super::MAX_WITHDRAW_FEE_BPS;
+use super::MULTIPLIER_BASE;
 use super::SHARE;
 
 type Hash = u128;
 
-// Maximum number of reward 'events' that can be processed in one request to prevent to prevent excessive consumption of resources
-const INDEX_MAX_DELTA: u32 = 1000;
+// Default maximum number of reward 'events' that can be processed in one request to prevent excessive consumption of resources
+pub const INDEX_MAX_DELTA: u32 = 1000;
+
+// Fixed-point base of the accumulated reward per unit of support
+const REWARD_PER_UNIT_PRECISION: u128 = 1_000_000_000_000;
+
+// Maximum number of support indexes summarized in one withdrawable request
+const WITHDRAWABLE_MAX_ENTRIES: usize = 100;
+
+// Maximum number of rewards returned by one reward history request
+pub const REWARD_HISTORY_MAX_ENTRIES: usize = 1000;
 
 pub struct Hasher {}
 
 impl Hasher {
-    fn hash(index: Index, address: Address) -> u128 {
-        index as u128 + address
+    fn hash(index: RewardIndex, address: Address) -> u128 {
+        index.0 as u128 + address
     }
 }
 
+// Reward of the support at the index it is deposited at
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RewardMode {
+    // Whole reward of the index
+    #[default]
+    Flat,
+    // Reward of the index pro-rated by the part of the index period after the deposit
+    TimeWeighted,
+}
+
 pub struct User {
     // User address
     pub address: Address,
@@ -26,134 +60,2783 @@ pub struct User {
     pub balance: Amount,
 }
 
+#[derive(Clone)]
 pub struct Validator {
     // Total token balance for that validator
     pub total_balance: Amount,
     // Current reward index for that validator (some sort of timestamp or reward-block-number), incremented
-    pub current_index: Index,
-    // Total tokens support for some reward by its index
-    pub total_support: HashMap<Index, Amount>,
+    pub current_index: RewardIndex,
+    // Total tokens support change points ordered by index: support from that index on
+    pub total_support: Vec<(RewardIndex, Amount)>,
     // Reward by its index
-    pub reward: HashMap<Index, Amount>,
+    pub reward: HashMap<RewardIndex, Amount>,
     // User support deposited at some reward index - Hash(reward_index, user_address)
     pub user_support: HashMap<Hash, Amount>,
     // User support where the user has money
-    pub user_support_indexes: HashMap<Address, Vec<Index>>,
+    pub user_support_indexes: HashMap<Address, Vec<RewardIndex>>,
+    // Minimal amount of a single vote (DEFAULT_MIN_VOTE by default)
+    pub min_vote: Amount,
+    // Maximum total support for that validator, unlimited if none
+    pub max_total_delegated: Option<Amount>,
+    // Maximum number of delegators, unlimited if none
+    pub max_delegators: Option<usize>,
+    // Support amount from which a user gets the tier multiplier, no tiers if none
+    pub tier_threshold: Option<Amount>,
+    // Reward multiplier for the users above the tier threshold (MULTIPLIER_BASE is 1.0)
+    pub tier_rate: Amount,
+    // Indicates that votes and rewards are not accepted, withdrawals are still available
+    pub paused: bool,
+    // Users whose rewards are added to their support at the current index instead of being sent
+    pub auto_compound: HashSet<Address>,
+    // Rounding of user rewards
+    pub rounding: RoundingPolicy,
+    // Accumulated rounding remainder of user rewards in DUST_PRECISION fractions of a token
+    pub dust: Amount,
+    // Lifecycle state, votes and rewards are accepted only by active validators
+    pub state: ValidatorState,
+    // External id of the last appended reward (block number or event id)
+    pub last_reward_id: Option<u64>,
+    // External reward id by its index
+    pub reward_source: HashMap<RewardIndex, u64>,
+    // Sum of rewards per unit of support over all indexes before the key (REWARD_PER_UNIT_PRECISION is 1.0)
+    pub reward_per_unit: HashMap<RewardIndex, Amount>,
+    // Minimal reward that gets its own index, smaller rewards are collected in the bucket
+    pub min_reward: Amount,
+    // Collected rewards that have not reached the minimal reward yet
+    pub reward_bucket: Amount,
+    // Number of rewards per year used to annualize the estimated APR
+    pub rewards_per_year: u32,
+    // Total amount sent to each user: principal and rewards
+    pub withdrawn: HashMap<Address, Amount>,
+    // State version, incremented on every change
+    pub version: u64,
+    // Number of indexes the support stays locked after the index it is deposited at
+    pub lockup_rewards: Index,
+    // Index the user support unlocks at if it has been moved from the index it is deposited at.
+    // Support moved by claims and continuations keeps the unlock index of its deposit
+    pub unlocks_at: HashMap<Hash, RewardIndex>,
+    // Fee on withdrawn principal in basis points, up to MAX_WITHDRAW_FEE_BPS
+    pub withdraw_fee_bps: u32,
+    // Withdrawal fees that stay on the balance until the owner takes them
+    pub collected_fees: Amount,
+    // Maximum number of indexes processed in one request (INDEX_MAX_DELTA by default)
+    pub max_index_delta: Index,
+    // Log of committed operations
+    pub audit: AuditLog,
+    // Reward of the support at the index it is deposited at
+    pub reward_mode: RewardMode,
+    // Unix time each index has started at
+    pub index_started_at: HashMap<RewardIndex, u64>,
+    // Unix time the user support has been deposited at within its index, weighted by amount.
+    // Kept in the time-weighted mode only
+    pub deposited_at: HashMap<Hash, u64>,
 }
 
-trait Democracy {
-    fn vote(&mut self, user: &mut User, amount: Amount) -> (Index, Amount);
-}
+impl Validator {
+    // Empty validator with the given parameters
+    pub fn new(config: &ValidatorConfig) -> Result<Validator, ConfigError> {
+        config.validate()?;
+
+        Ok(Validator {
+            total_balance: 0,
+            current_index: RewardIndex::default(),
+            total_support: vec![],
+            reward: HashMap::new(),
+            user_support: HashMap::new(),
+            user_support_indexes: HashMap::new(),
+            min_vote: config.min_vote,
+            max_total_delegated: config.max_total_delegated,
+            max_delegators: config.max_delegators,
+            tier_threshold: config.tier_threshold,
+            tier_rate: config.tier_rate,
+            paused: false,
+            auto_compound: HashSet::new(),
+            rounding: config.rounding,
+            dust: 0,
+            state: ValidatorState::Active,
+            last_reward_id: None,
+            reward_source: HashMap::new(),
+            reward_per_unit: HashMap::new(),
+            min_reward: config.min_reward,
+            reward_bucket: 0,
+            rewards_per_year: config.rewards_per_year,
+            withdrawn: HashMap::new(),
+            version: 0,
+            lockup_rewards: config.lockup_rewards,
+            unlocks_at: HashMap::new(),
+            withdraw_fee_bps: config.withdraw_fee_bps,
+            collected_fees: 0,
+            max_index_delta: config.max_index_delta,
+            audit: AuditLog::new(config.audit_capacity),
+            reward_mode: config.reward_mode,
+            index_started_at: HashMap::from([(RewardIndex::default(), unix_time())]),
+            deposited_at: HashMap::new(),
+        })
+    }
+
+    // Reward multiplier for a user with the given stake: MULTIPLIER_BASE unless it reaches the tier threshold
+    pub fn tier_multiplier(&self, stake: Amount) -> Amount {
+        match self.tier_threshold {
+            Some(threshold) if stake >= threshold => self.tier_rate,
+            _ => MULTIPLIER_BASE,
+        }
+    }
+
+    // Number of users with support. Addresses are removed from user support indexes once their support is
+    // fully withdrawn
+    pub fn delegator_count(&self) -> usize {
+        self.user_support_indexes.len()
+    }
+
+    // Addresses of users with support ordered by address
+    pub fn delegator_list(&self, offset: usize, limit: usize) -> Vec<Address> {
+        let mut delegators: Vec<Address> = self.user_support_indexes.keys().cloned().collect();
+        delegators.sort_unstable();
+
+        delegators.into_iter().skip(offset).take(limit).collect()
+    }
+
+    // Rewards that are waiting for the minimal reward to be appended at an index
+    pub fn pending_reward(&self) -> Amount {
+        self.reward_bucket
+    }
+
+    // Reward appended at index
+    pub fn reward_for_index(&self, index: RewardIndex) -> Option<Amount> {
+        self.reward.get(&index).cloned()
+    }
+
+    // Rewards appended at indexes from..to ordered by index, no more than limit of them and never more than
+    // REWARD_HISTORY_MAX_ENTRIES. The page tells the index the next request starts at if there are rewards left
+    pub fn reward_history(
+        &self,
+        from: RewardIndex,
+        to: RewardIndex,
+        limit: usize,
+    ) -> RewardHistoryPage {
+        let limit = cmp::min(limit, REWARD_HISTORY_MAX_ENTRIES);
+
+        let mut entries = vec![];
+        for index in from.until(cmp::min(to, self.current_index)) {
+            let reward = match self.reward.get(&index) {
+                Some(reward) => *reward,
+                None => continue,
+            };
+            if entries.len() == limit {
+                return RewardHistoryPage {
+                    entries,
+                    next_from: Some(index),
+                };
+            }
+            entries.push((index, reward));
+        }
+
+        RewardHistoryPage {
+            entries,
+            next_from: None,
+        }
+    }
+
+    // Estimated annual users reward rate in basis points over the last window indexes (or less if there are
+    // not enough of them). None if there is no history or no support to estimate it
+    pub fn estimated_apr_bps(&self, window: Index) -> Option<u32> {
+        let from = self.current_index.before(window);
+
+        // Users part of the rewards and the support that earned them
+        let mut rewards = 0;
+        let mut supported = 0;
+        for index in from.until(self.current_index) {
+            rewards += self.reward.get(&index).cloned().unwrap_or(0) * SHARE / 100;
+            supported += self.support_at(index);
+        }
+
+        if supported == 0 || self.rewards_per_year == 0 {
+            return None;
+        }
+
+        // Average reward per index divided by average support, annualized
+        let apr = rewards * self.rewards_per_year as u128 * 10_000 / supported;
+        Some(cmp::min(apr, u32::MAX as u128) as u32)
+    }
+
+    // Users part of the rewards at every new index if the future rewards were appended. Rewards are applied
+    // to a copy of the state, the validator itself is not changed
+    pub fn project(&self, future_rewards: &[Amount]) -> Vec<(RewardIndex, Amount)> {
+        let mut projected = self.clone();
+        let first_index = projected.current_index;
+
+        // The copy accepts rewards whatever the lifecycle of the validator is
+        projected.paused = false;
+        projected.state = ValidatorState::Active;
+
+        if !future_rewards.is_empty() {
+            let first_reward_id = projected.last_reward_id.map_or(0, |id| id + 1);
+            projected.append_rewards(
+                (first_reward_id..)
+                    .zip(future_rewards.iter().cloned())
+                    .collect(),
+            );
+        }
+
+        first_index
+            .until(projected.current_index)
+            .filter_map(|index| {
+                let reward = projected.reward.get(&index)?;
+                Some((index, reward * SHARE / 100))
+            })
+            .collect()
+    }
+
+    // Total support at index, none for indexes after the current one
+    pub fn total_support_at(&self, index: RewardIndex) -> Option<Amount> {
+        if index > self.current_index {
+            return None;
+        }
+
+        Some(self.support_at(index))
+    }
+
+    // Total support in force at index: the value of the last change point at or before it
+    pub fn support_at(&self, index: RewardIndex) -> Amount {
+        match self
+            .total_support
+            .binary_search_by_key(&index, |(point, _)| *point)
+        {
+            Ok(position) => self.total_support[position].1,
+            Err(0) => 0,
+            Err(position) => self.total_support[position - 1].1,
+        }
+    }
+
+    // Rewards that all users would get if they withdrew everything at the current index.
+    // Processes every support index of every user, so it is O(delegators * indexes) and should not be called
+    // on the hot path
+    pub fn total_pending_rewards(&self) -> Amount {
+        let mut pending = 0;
+        for (address, indexes) in self.user_support_indexes.iter() {
+            let stake = self.stake_of(*address);
+            for index in indexes {
+                let supported = self
+                    .user_support
+                    .get(&Hasher::hash(*index, *address))
+                    .cloned()
+                    .unwrap_or(0);
+                let delta = self.current_index.delta(*index);
+                let (_, reward, _) =
+                    self.accumulate_reward(*address, *index, supported, stake, delta);
+                pending += reward;
+            }
+        }
+
+        pending
+    }
+
+    // Stop accepting votes and rewards for good, users can only withdraw
+    pub fn begin_deactivation(&mut self) {
+        if self.state != ValidatorState::Active {
+            panic!("Validator is not active");
+        }
+
+        self.state = ValidatorState::Deactivating;
+        self.version += 1;
+    }
+
+    // Send the rest of the balance to the owner once all users have withdrawn their support
+    pub fn finalize_deactivation(&mut self, owner: &mut User) -> Amount {
+        if self.state != ValidatorState::Deactivating {
+            panic!("Validator is not deactivating");
+        }
+
+        if !self.user_support_indexes.is_empty() {
+            panic!("Users have not withdrawn everything yet");
+        }
+
+        // Send the rest: dust, fees and undistributed rewards
+        let rest = self.total_balance;
+        self.total_balance = 0;
+        self.collected_fees = 0;
+        owner.balance += rest;
+
+        self.state = ValidatorState::Closed;
+        self.version += 1;
+
+        rest
+    }
+
+    // Set the fee on withdrawn principal
+    pub fn set_withdraw_fee(&mut self, fee_bps: u32) {
+        if fee_bps > MAX_WITHDRAW_FEE_BPS {
+            panic!(
+                "Withdraw fee is above the maximum of {} bps",
+                MAX_WITHDRAW_FEE_BPS
+            );
+        }
+
+        self.withdraw_fee_bps = fee_bps;
+        self.version += 1;
+    }
+
+    // Send the collected withdrawal fees to the owner
+    pub fn withdraw_fees(&mut self, owner: &mut User) -> Amount {
+        let fees = self.collected_fees;
+        self.collected_fees = 0;
+
+        self.total_balance -= fees;
+        owner.balance += fees;
+        self.version += 1;
+
+        fees
+    }
+
+    // Send whole tokens of the accumulated dust to the owner, the fraction stays in the dust
+    pub fn sweep_dust(&mut self, owner: &mut User) -> Amount {
+        let swept = self.dust / DUST_PRECISION;
+        self.dust %= DUST_PRECISION;
+
+        self.total_balance -= swept;
+        owner.balance += swept;
+        self.version += 1;
+
+        swept
+    }
+
+    // Stop accepting votes and rewards, withdrawals are still available
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.version += 1;
+    }
+
+    // Accept votes and rewards again
+    pub fn unpause(&mut self) {
+        self.paused = false;
+        self.version += 1;
+    }
+
+    // Remove entries that no withdrawal can read anymore: zero user support, and total support and rewards
+    // before both keep_after and the lowest index any user still has support at. Returns the number of removed entries
+    pub fn compact(&mut self, keep_after: RewardIndex) -> usize {
+        let entries = self.user_support.len()
+            + self.total_support.len()
+            + self.reward.len()
+            + self.reward_source.len()
+            + self.reward_per_unit.len()
+            + self.index_started_at.len();
+
+        self.user_support.retain(|_, supported| *supported > 0);
+
+        // Withdrawals process indexes from the ones users have support at, the current index is always kept
+        let lowest_index = self
+            .user_support_indexes
+            .values()
+            .filter_map(|indexes| indexes.first().cloned())
+            .min()
+            .unwrap_or(self.current_index);
+        let bound = cmp::min(cmp::min(keep_after, lowest_index), self.current_index);
+
+        // Keep the support in force at the bound as its first change point
+        let support = self.support_at(bound);
+        self.total_support.retain(|(index, _)| *index >= bound);
+        if self.total_support.first().map(|(index, _)| *index) != Some(bound) {
+            self.total_support.insert(0, (bound, support));
+        }
+        self.reward.retain(|index, _| *index >= bound);
+        self.reward_source.retain(|index, _| *index >= bound);
+        self.reward_per_unit.retain(|index, _| *index >= bound);
+        self.index_started_at.retain(|index, _| *index >= bound);
+        self.version += 1;
+
+        entries
+            - self.user_support.len()
+            - self.total_support.len()
+            - self.reward.len()
+            - self.reward_source.len()
+            - self.reward_per_unit.len()
+            - self.index_started_at.len()
+    }
+
+    // Enable or disable adding of user rewards to the user support
+    pub fn set_auto_compound(&mut self, user: &User, enabled: bool) {
+        // Check that validator is not paused
+        if self.paused {
+            panic!("Validator is paused");
+        }
+
+        if enabled {
+            self.auto_compound.insert(user.address);
+        } else {
+            self.auto_compound.remove(&user.address);
+        }
+        self.version += 1;
+    }
+
+    // Set total support from index on until the next change point
+    fn set_support(&mut self, index: RewardIndex, amount: Amount) {
+        self.version += 1;
+
+        match self
+            .total_support
+            .binary_search_by_key(&index, |(point, _)| *point)
+        {
+            Ok(position) => self.total_support[position].1 = amount,
+            Err(position) => self.total_support.insert(position, (index, amount)),
+        }
+    }
+
+    // Statement of the user support at every index, rewards earned by it and everything sent to the user
+    pub fn user_statement(&self, address: Address) -> UserStatement {
+        let indexes = self
+            .user_support_indexes
+            .get(&address)
+            .cloned()
+            .unwrap_or_default();
+
+        let stake = self.stake_of(address);
+        let entries: Vec<StatementEntry> = indexes
+            .into_iter()
+            .map(|index| {
+                let supported = self
+                    .user_support
+                    .get(&Hasher::hash(index, address))
+                    .cloned()
+                    .unwrap_or(0);
+                let (_, reward, _) = self.accumulate_reward(
+                    address,
+                    index,
+                    supported,
+                    stake,
+                    self.current_index.delta(index),
+                );
+                StatementEntry {
+                    index,
+                    supported,
+                    reward,
+                }
+            })
+            .collect();
+
+        UserStatement {
+            address,
+            pending_reward: entries.iter().map(|entry| entry.reward).sum(),
+            entries,
+            withdrawn: self.withdrawn.get(&address).cloned().unwrap_or(0),
+        }
+    }
+
+    // Move the support of one address at every index to another one. The destination takes the place of the
+    // source among the delegators, so the delegator limit can't be exceeded. A new delegator takes over the auto
+    // compounding setting of the source, an existing one keeps its own
+    pub fn transfer_support(&mut self, from: Address, to: Address) {
+        if from == to {
+            panic!("Support can't be transferred to the same address");
+        }
+
+        let indexes = self.user_support_indexes.get(&from).cloned();
+        if indexes.is_none() {
+            panic!("No support to transfer")
+        }
+
+        let compounding = self.auto_compound.remove(&from);
+        if compounding && !self.is_delegator(to) {
+            self.auto_compound.insert(to);
+        }
+        self.version += 1;
+
+        for index in indexes.unwrap() {
+            let supported = self
+                .user_support
+                .get(&Hasher::hash(index, from))
+                .cloned()
+                .unwrap_or(0);
+            let to_supported = self
+                .user_support
+                .get(&Hasher::hash(index, to))
+                .cloned()
+                .unwrap_or(0);
+
+            // Merged support unlocks at the later of both unlock indexes
+            let unlocks_at = if to_supported > 0 {
+                cmp::max(self.unlock_index(from, index), self.unlock_index(to, index))
+            } else {
+                self.unlock_index(from, index)
+            };
+
+            // The support keeps its deposit time, it is weighted with the destination one
+            let deposited = self
+                .deposited_at
+                .get(&Hasher::hash(index, from))
+                .or_else(|| self.index_started_at.get(&index))
+                .cloned()
+                .unwrap_or(0);
+
+            self.set_user_support(from, index, 0);
+            self.add_user_support(to, index, supported, deposited);
+            self.unlocks_at.insert(Hasher::hash(index, to), unlocks_at);
+        }
+    }
+
+    // Number of withdrawal rounds needed to process indexes from index to the current one, every round processes
+    // up to max index delta indexes. Zero if there is nothing to process
+    pub fn estimate_withdraw_rounds(&self, from_index: RewardIndex) -> u32 {
+        self.current_index
+            .0
+            .saturating_sub(from_index.0)
+            .div_ceil(self.max_index_delta)
+    }
+
+    // Support indexes of the user with the principal and the reward one withdrawal from that index would pay,
+    // no more than WITHDRAWABLE_MAX_ENTRIES of them
+    pub fn withdrawable(&self, address: Address) -> Vec<WithdrawableEntry> {
+        let indexes = self
+            .user_support_indexes
+            .get(&address)
+            .cloned()
+            .unwrap_or_default();
+
+        let stake = self.stake_of(address);
+        indexes
+            .into_iter()
+            .take(WITHDRAWABLE_MAX_ENTRIES)
+            .map(|index| {
+                let principal = self
+                    .user_support
+                    .get(&Hasher::hash(index, address))
+                    .cloned()
+                    .unwrap_or(0);
+                let (_, estimated_reward, _) =
+                    self.accumulate_reward(address, index, principal, stake, self.max_index_delta);
+                WithdrawableEntry {
+                    index,
+                    principal,
+                    estimated_reward,
+                }
+            })
+            .collect()
+    }
+
+    // Indicates that the user has support at some index
+    pub fn is_delegator(&self, address: Address) -> bool {
+        self.user_support_indexes.contains_key(&address)
+    }
+
+    // User support summed across the user support indexes
+    pub fn stake_of(&self, address: Address) -> Amount {
+        self.user_support_indexes
+            .get(&address)
+            .map(|indexes| {
+                indexes
+                    .iter()
+                    .filter_map(|index| self.user_support.get(&Hasher::hash(*index, address)))
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    // Users with the largest support summed across their support indexes, with their part of the total support
+    // in basis points
+    pub fn top_delegators(&self, n: usize) -> Vec<(Address, Amount, u32)> {
+        let total_supported = self.support_at(self.current_index);
+        let mut delegators: Vec<(Address, Amount, u32)> = self
+            .user_support_indexes
+            .keys()
+            .map(|address| {
+                let stake = self.stake_of(*address);
+                let share_bps = (stake * 10_000).checked_div(total_supported).unwrap_or(0);
+                (*address, stake, share_bps as u32)
+            })
+            .collect();
 
-trait RewardSharing {
-    fn append_reward(&mut self, reward: Amount);
-    fn try_withdraw_with_rewards(
+        // Largest stakes first, equal stakes ordered by address
+        delegators.sort_unstable_by(|(address_a, amount_a, _), (address_b, amount_b, _)| {
+            amount_b.cmp(amount_a).then(address_a.cmp(address_b))
+        });
+        delegators.truncate(n);
+
+        delegators
+    }
+
+    // Check that a new delegator fits into the limit, existing ones can always vote
+    fn check_delegator(&self, address: Address) -> Result<(), DposError> {
+        if let Some(max_delegators) = self.max_delegators {
+            if !self.user_support_indexes.contains_key(&address)
+                && self.delegator_count() >= max_delegators
+            {
+                return Err(DposError::DelegatorLimitReached(max_delegators));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check that validator accepts votes and the amount can be supported
+    fn check_vote_amount(&self, user: &User, amount: Amount) -> Result<(), DposError> {
+        // Check that validator accepts votes
+        if self.paused {
+            return Err(DposError::Paused);
+        }
+
+        // Check that validator is active
+        if self.state != ValidatorState::Active {
+            return Err(DposError::NotActive);
+        }
+
+        // Reject zero and dust votes
+        if amount < self.min_vote {
+            return Err(DposError::BelowMinVote(self.min_vote));
+        }
+
+        // User must have the amount
+        if amount > user.balance {
+            return Err(DposError::InsufficientBalance(user.balance));
+        }
+
+        // Check that the new total support fits into the cap
+        let available = self.available_to_delegate();
+        if amount > available {
+            return Err(DposError::CapExceeded(available));
+        }
+
+        Ok(())
+    }
+
+    // Part of the reward that is added to the user support instead of being sent: nothing unless the user compounds
+    // rewards and the amount stays delegated, and no more than fits under the delegation cap
+    fn compounded_part(&self, address: Address, reward: Amount, continuation: bool) -> Amount {
+        if !continuation || !self.auto_compound.contains(&address) {
+            return 0;
+        }
+
+        cmp::min(reward, self.available_to_delegate())
+    }
+
+    // Amount the total support can still grow by under the delegation cap, unlimited without the cap
+    fn available_to_delegate(&self) -> Amount {
+        match self.max_total_delegated {
+            Some(max_total_delegated) => {
+                max_total_delegated.saturating_sub(self.support_at(self.current_index))
+            }
+            None => Amount::MAX,
+        }
+    }
+
+    // Check that validator accepts the reward
+    fn check_reward(&self, reward_id: u64) -> Result<(), DposError> {
+        // Check that validator is not paused
+        if self.paused {
+            return Err(DposError::Paused);
+        }
+
+        // Check that validator is active
+        if self.state != ValidatorState::Active {
+            return Err(DposError::NotActive);
+        }
+
+        // Reject rewards that have already been appended
+        if self.last_reward_id.is_some_and(|last| reward_id <= last) {
+            return Err(DposError::DuplicateReward(reward_id));
+        }
+
+        Ok(())
+    }
+
+    // Index the user support at index unlocks at
+    pub fn unlock_index(&self, address: Address, index: RewardIndex) -> RewardIndex {
+        self.unlocks_at
+            .get(&Hasher::hash(index, address))
+            .cloned()
+            .unwrap_or(index.after(self.lockup_rewards))
+    }
+
+    // Add the amount deposited at the given unix time to the user support at index. Returns the updated support
+    fn add_user_support(
         &mut self,
-        user: &mut User,
-        from_index: Index,
+        address: Address,
+        index: RewardIndex,
         amount: Amount,
-    ) -> Option<(Index, Amount)>;
-}
+        at: u64,
+    ) -> Amount {
+        let hash = Hasher::hash(index, address);
+        let supported = self.user_support.get(&hash).cloned().unwrap_or(0);
+        let update = supported + amount;
 
-impl Democracy for Validator {
-    fn vote(&mut self, user: &mut User, amount: Amount) -> (Index, Amount) {
-        // Update total balance
-        self.total_balance += amount;
+        // Deposit time of the whole support is the amount-weighted time of its parts
+        if self.reward_mode == RewardMode::TimeWeighted && update > 0 {
+            let started = self.index_started_at.get(&index).cloned().unwrap_or(at);
+            let deposited = self.deposited_at.get(&hash).cloned().unwrap_or(started);
+            let weighted = (deposited as u128 * supported + at as u128 * amount) / update;
+            self.deposited_at.insert(hash, weighted as u64);
+        }
 
-        // Update total support at current index
-        let update = match self.total_support.get(&self.current_index) {
-            Some(supported) => supported + amount,
-            None => amount,
-        };
-        self.total_support.insert(self.current_index, update);
+        self.set_user_support(address, index, update);
 
-        // Get hash from address and current index
-        let hash = Hasher::hash(self.current_index, user.address);
+        update
+    }
 
-        // Update user balance at current index
-        let update = match self.user_support.get(&hash) {
-            Some(supported) => supported + amount,
-            None => amount,
-        };
+    // Part of the index period before the user deposit and the whole period in seconds. None if the deposit earns
+    // the whole reward of the index: in the flat mode or if the index has not ended before end index
+    fn late_deposit(
+        &self,
+        address: Address,
+        index: RewardIndex,
+        end_index: RewardIndex,
+    ) -> Option<(u64, u64)> {
+        if self.reward_mode != RewardMode::TimeWeighted || end_index <= index {
+            return None;
+        }
+
+        let deposited = *self.deposited_at.get(&Hasher::hash(index, address))?;
+        let started = *self.index_started_at.get(&index)?;
+        let ended = *self.index_started_at.get(&index.succ())?;
+        if ended <= started {
+            return None;
+        }
 
-        self.user_support.insert(hash, update);
+        let period = ended - started;
+        Some((cmp::min(deposited.saturating_sub(started), period), period))
+    }
 
-        user.balance -= amount;
+    // Update user support at index and keep the user support indexes in sync with it
+    fn set_user_support(&mut self, address: Address, index: RewardIndex, amount: Amount) {
+        self.version += 1;
 
-        // Return current index and updated support amount for user
-        (self.current_index, update)
+        self.user_support
+            .insert(Hasher::hash(index, address), amount);
+        if amount == 0 {
+            self.deposited_at.remove(&Hasher::hash(index, address));
+            self.unlocks_at.remove(&Hasher::hash(index, address));
+        }
+
+        let indexes = self.user_support_indexes.entry(address).or_default();
+        match indexes.binary_search(&index) {
+            Ok(position) if amount == 0 => {
+                indexes.remove(position);
+            }
+            Err(position) if amount > 0 => indexes.insert(position, index),
+            _ => {}
+        }
+
+        if indexes.is_empty() {
+            self.user_support_indexes.remove(&address);
+        }
     }
-}
 
-impl RewardSharing for Validator {
-    fn append_reward(&mut self, reward: Amount) {
-        // Insert new index support - its Amount is current total balance
-        self.total_support.insert(
-            self.current_index + 1,
-            self.total_support
-                .get(&self.current_index)
-                .cloned()
-                .unwrap_or(0),
+    // Reward for the amount deposited at index for no more than max_delta indexes, the upper bound index
+    // and the rounding remainder of the reward. The tier is picked from the whole stake of the user, not from
+    // the amount. Computed as a difference of the rewards per unit of support at the bounds, so previews take
+    // the same constant time whatever the number of indexes
+    fn accumulate_reward(
+        &self,
+        address: Address,
+        from_index: RewardIndex,
+        amount: Amount,
+        stake: Amount,
+        max_delta: Index,
+    ) -> (RewardIndex, Amount, i128) {
+        // Accumulate rewards until the current or max possible index
+        let max_index = from_index.after(max_delta);
+        let end_index = cmp::min(max_index, self.current_index);
+
+        // User share of the reward with the tier multiplier applied. The bonus is taken from the owner share,
+        // so it can't exceed the whole reward
+        let share = cmp::min(SHARE * self.tier_multiplier(stake) / MULTIPLIER_BASE, 100);
+
+        // Reward per unit of support earned between the indexes
+        let reward_per_unit =
+            self.reward_per_unit_at(end_index) - self.reward_per_unit_at(from_index);
+        let (reward, remainder) = self.rounding.divide(
+            reward_per_unit * share * amount,
+            REWARD_PER_UNIT_PRECISION * 100,
         );
 
-        // Update index
-        self.current_index += 1;
+        // The part of the first index reward before a late deposit is not earned, it stays on the balance
+        let reward = match self.late_deposit(address, from_index, end_index) {
+            Some((late, period)) => {
+                let first_reward_per_unit = self.reward_per_unit_at(from_index.succ())
+                    - self.reward_per_unit_at(from_index);
+                let (first_reward, _) = self.rounding.divide(
+                    first_reward_per_unit * share * amount,
+                    REWARD_PER_UNIT_PRECISION * 100,
+                );
+                reward.saturating_sub(first_reward * late as u128 / period as u128)
+            }
+            None => reward,
+        };
 
-        // Update total balance
-        self.total_balance += reward;
+        (end_index, reward, remainder)
+    }
+
+    // Sum of rewards per unit of support over all indexes before index
+    fn reward_per_unit_at(&self, index: RewardIndex) -> Amount {
+        self.reward_per_unit.get(&index).cloned().unwrap_or(0)
+    }
+
+    // Take the withdrawal fee from the principal, the fee stays on the balance. Returns the principal left to send
+    fn take_withdraw_fee(&mut self, principal: Amount) -> Amount {
+        let fee = withdraw_fee(principal, self.withdraw_fee_bps);
+        self.collected_fees += fee;
+
+        principal - fee
     }
 
-    fn try_withdraw_with_rewards(
+    // Send amount from the validator balance to the user
+    fn send(&mut self, user: &mut User, amount: Amount) {
+        self.version += 1;
+
+        self.total_balance -= amount;
+        user.balance += amount;
+
+        *self.withdrawn.entry(user.address).or_insert(0) += amount;
+    }
+
+    // Withdraw the amount deposited at index with rewards for no more than max_delta indexes.
+    // If the amount is kept or the support at index is still locked only the reward is sent and the amount is placed
+    // to the upper bound index, it keeps the unlock index of the support at index
+    fn withdraw_with_rewards(
         &mut self,
         user: &mut User,
-        from_index: Index,
+        from_index: RewardIndex,
         amount: Amount,
-    ) -> Option<(Index, Amount)> {
+        stake: Amount,
+        max_delta: Index,
+        keep_amount: bool,
+    ) -> Option<(RewardIndex, Amount)> {
         // Get hash from address and current index
         let hash = Hasher::hash(from_index, user.address);
 
         // Get user support balance at index
         let supported = self.user_support.get(&hash).cloned().unwrap();
 
-        // Accumulate rewards until the current or max possible index
-        let max_index = from_index + INDEX_MAX_DELTA;
-        let end_index = cmp::max(max_index, self.current_index);
+        let (end_index, reward, remainder) =
+            self.accumulate_reward(user.address, from_index, amount, stake, max_delta);
+        self.dust = add_dust(self.dust, remainder);
 
-        let mut reward = 0;
-        for i in from_index..end_index {
-            let user_share = amount / self.total_support.get(&i)?;
-            reward += self.reward.get(&i).cloned().unwrap_or(0) * SHARE * user_share / 100;
-        }
+        // Unlock indexes of the amount and of the support that is already at the upper bound index
+        let unlocks_at = self.unlock_index(user.address, from_index);
+        let end_supported = self
+            .user_support
+            .get(&Hasher::hash(end_index, user.address))
+            .cloned()
+            .unwrap_or(0);
+        let end_unlocks_at = if end_supported > 0 && end_index != from_index {
+            Some(self.unlock_index(user.address, end_index))
+        } else {
+            None
+        };
 
         // Update supporter balance at index: subtract provided amount
-        self.user_support.insert(hash, supported - amount);
+        self.set_user_support(user.address, from_index, supported - amount);
+
+        // Make a decision - how much to withdraw depending on processed indexes length and the lockup
+        let locked = self.current_index < unlocks_at;
+        let continuation = end_index < self.current_index || locked || keep_amount;
+
+        // If user compounds rewards and the amount stays delegated - add the reward to user and total support at
+        // current index, it stays on balance. Only the part that fits under the delegation cap is added, the rest
+        // is sent
+        let compounded = self.compounded_part(user.address, reward, continuation);
+        if compounded > 0 {
+            self.add_user_support(user.address, self.current_index, compounded, unix_time());
+
+            let total_supported = self.support_at(self.current_index);
+            self.set_support(self.current_index, total_supported + compounded);
+        }
+        let reward = reward - compounded;
 
-        // Make a decision - how much to withdraw depending on processed indexes length
-        if end_index < self.current_index {
+        if continuation {
             // If there are rewards left after the last processed index -
             // place the provided amount to the upper bound index and withdraw only reward
-            let hash = Hasher::hash(end_index, user.address);
-
-            let new_balance = match self.user_support.get(&hash) {
-                Some(balance) => balance + amount,
-                None => amount,
-            };
+            // The amount has been supported since the start of the upper bound index
+            let started = self.index_started_at.get(&end_index).cloned().unwrap_or(0);
+            let new_balance = self.add_user_support(user.address, end_index, amount, started);
 
-            self.user_support.insert(hash, new_balance);
+            // Merged support unlocks at the later of both unlock indexes
+            let unlocks_at = end_unlocks_at.map_or(unlocks_at, |end| cmp::max(end, unlocks_at));
+            self.unlocks_at
+                .insert(Hasher::hash(end_index, user.address), unlocks_at);
 
             // Send only the reward
-            self.total_balance -= reward;
-            user.balance += reward;
+            self.send(user, reward);
+            self.audit
+                .record(AuditOp::Withdraw, Some(user.address), reward, end_index.0);
 
             // Return updated upper bound index
             Some((end_index, new_balance))
         } else {
-            // Withdraw all
-            self.total_balance -= amount + reward;
-            user.balance += amount + reward;
+            // Update total support at current index - the amount no longer takes part in rewards
+            let update = self.support_at(self.current_index) - amount;
+            self.set_support(self.current_index, update);
+
+            // Withdraw all, the fee is taken from the principal only
+            let principal = self.take_withdraw_fee(amount);
+            self.send(user, principal + reward);
+            self.audit.record(
+                AuditOp::Withdraw,
+                Some(user.address),
+                principal + reward,
+                end_index.0,
+            );
 
             // Return none - everything has been withdrawn
             None
         }
     }
 }
+
+#[derive(Debug, Serialize)]
+pub struct RewardHistoryPage {
+    // Index and reward of every returned index
+    pub entries: Vec<(RewardIndex, Amount)>,
+    // Index to request the next page from, none if the range is exhausted
+    pub next_from: Option<RewardIndex>,
+}
+
+pub struct WithdrawableEntry {
+    // Index the support is deposited at
+    pub index: RewardIndex,
+    // User support at index
+    pub principal: Amount,
+    // Reward of one withdrawal from that index
+    pub estimated_reward: Amount,
+}
+
+#[derive(Serialize)]
+pub struct StatementEntry {
+    // Index the support is deposited at
+    pub index: RewardIndex,
+    // User support at index
+    pub supported: Amount,
+    // Reward earned by the support up to the current index
+    pub reward: Amount,
+}
+
+#[derive(Serialize)]
+pub struct UserStatement {
+    // User address, 0x prefixed hex when serialized
+    #[serde(serialize_with = "super::address::serialize")]
+    pub address: Address,
+    // User support by index
+    pub entries: Vec<StatementEntry>,
+    // Total amount sent to the user: principal and rewards
+    pub withdrawn: Amount,
+    // Reward the user would get withdrawing everything at the current index
+    pub pending_reward: Amount,
+}
+
+impl UserStatement {
+    // Statement entries as CSV with a header line
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("index,supported,reward\n");
+        for entry in self.entries.iter() {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                entry.index, entry.supported, entry.reward
+            ));
+        }
+
+        csv
+    }
+}
+
+pub struct WithdrawPreview {
+    // Amount that would be sent to the user: the reward, and the principal if everything is withdrawn
+    pub payout: Amount,
+    // Reward that would be added to the user support instead of being sent if the user compounds rewards
+    pub compounded: Amount,
+    // Upper bound of the processed indexes
+    pub end_index: RewardIndex,
+    // Indicates that the amount would be placed to the upper bound index for the next withdrawal
+    pub continuation: bool,
+}
+
+pub struct WithdrawAllOutcome {
+    // Total amount sent to the user: principal and rewards
+    pub paid_out: Amount,
+    // Indicates that the work budget has been exhausted and another call is needed
+    pub incomplete: bool,
+}
+
+impl Validator {
+    // Deposit the amount at the current index. Returns the index and the user support at it
+    pub fn vote(&mut self, user: &mut User, amount: Amount) -> (RewardIndex, Amount) {
+        if let Err(error) = self.check_vote_amount(user, amount) {
+            panic!("{}", error);
+        }
+        if let Err(error) = self.check_delegator(user.address) {
+            panic!("{}", error);
+        }
+
+        // Update total balance
+        self.total_balance += amount;
+
+        // Update total support at current index
+        let delegated = self.support_at(self.current_index);
+        self.set_support(self.current_index, delegated + amount);
+
+        // Update user balance at current index, the whole support at it is locked from now on
+        let update = self.add_user_support(user.address, self.current_index, amount, unix_time());
+        self.unlocks_at
+            .remove(&Hasher::hash(self.current_index, user.address));
+
+        user.balance -= amount;
+
+        self.audit.record(
+            AuditOp::Vote,
+            Some(user.address),
+            amount,
+            self.current_index.0,
+        );
+
+        // Return current index and updated support amount for user
+        (self.current_index, update)
+    }
+
+    // Withdraw the amount deposited at index without rewards
+    pub fn unvote(&mut self, user: &mut User, from_index: RewardIndex, amount: Amount) {
+        // Get hash from address and the index where the support was deposited
+        let hash = Hasher::hash(from_index, user.address);
+
+        // Check that user has enough support at that index
+        let supported = self.user_support.get(&hash).cloned().unwrap_or(0);
+        if amount == 0 || amount > supported {
+            panic!("Not enough support at index {} to unvote", from_index);
+        }
+
+        // Check that the support is not locked
+        let unlocks_at = self.unlock_index(user.address, from_index);
+        if self.current_index < unlocks_at {
+            panic!(
+                "Support at index {} is locked until index {}",
+                from_index, unlocks_at
+            );
+        }
+
+        // Update user support at index: subtract provided amount
+        self.set_user_support(user.address, from_index, supported - amount);
+
+        // Update total support at current index - the amount no longer takes part in rewards
+        let update = self.support_at(self.current_index) - amount;
+        self.set_support(self.current_index, update);
+
+        // Return only the principal less the fee - no rewards are accrued
+        let principal = self.take_withdraw_fee(amount);
+        self.send(user, principal);
+
+        self.audit.record(
+            AuditOp::Unvote,
+            Some(user.address),
+            principal,
+            self.current_index.0,
+        );
+    }
+
+    // Share the reward with the support at the current index and start the next index
+    pub fn append_reward(&mut self, reward_id: u64, reward: Amount) {
+        if let Err(error) = self.check_reward(reward_id) {
+            panic!("{}", error);
+        }
+        self.last_reward_id = Some(reward_id);
+        self.version += 1;
+
+        // Update total balance
+        self.total_balance += reward;
+
+        // Collect small rewards until they reach the minimum, only then they get their own index
+        let collected = self.reward_bucket + reward;
+        if collected < self.min_reward {
+            self.reward_bucket = collected;
+            self.audit
+                .record(AuditOp::Reward, None, reward, self.current_index.0);
+            return;
+        }
+        self.reward_bucket = 0;
+
+        // Save reward and its external id for the support at current index
+        self.reward.insert(self.current_index, collected);
+        self.reward_source.insert(self.current_index, reward_id);
+
+        // Accumulate reward per unit of the support, nothing is shared if nobody has support
+        let supported = self.support_at(self.current_index);
+        let reward_per_unit = self.reward_per_unit_at(self.current_index)
+            + (collected * REWARD_PER_UNIT_PRECISION)
+                .checked_div(supported)
+                .unwrap_or(0);
+        self.reward_per_unit
+            .insert(self.current_index.succ(), reward_per_unit);
+
+        // Update index, support at the current index is in force for the new one
+        self.current_index = self.current_index.succ();
+        self.index_started_at
+            .insert(self.current_index, unix_time());
+
+        // The log records the appended reward, the collected ones have been recorded when they were appended
+        self.audit
+            .record(AuditOp::Reward, None, reward, self.current_index.0);
+    }
+
+    // Append rewards one by one, all ids are checked before anything is appended
+    pub fn append_rewards(
+        &mut self,
+        rewards: Vec<(u64, Amount)>,
+    ) -> Option<(RewardIndex, RewardIndex)> {
+        if rewards.is_empty() {
+            panic!("No rewards to append");
+        }
+
+        // Check all ids before appending anything: they must increase after the last appended one
+        let mut last_reward_id = self.last_reward_id;
+        for (reward_id, _) in rewards.iter() {
+            if last_reward_id.is_some_and(|last| *reward_id <= last) {
+                panic!("Duplicate reward {}", reward_id);
+            }
+            last_reward_id = Some(*reward_id);
+        }
+
+        // Every reward that reaches the minimal reward creates its own index
+        let previous_index = self.current_index;
+        for (reward_id, reward) in rewards {
+            self.append_reward(reward_id, reward);
+        }
+
+        // Return the first and the last index the rewards are recorded at, none if they have been collected in the bucket
+        if self.current_index == previous_index {
+            return None;
+        }
+
+        Some((previous_index, self.current_index.before(1)))
+    }
+
+    // Withdraw the amount deposited at index with rewards, computed against the given state version
+    pub fn try_withdraw_with_rewards(
+        &mut self,
+        user: &mut User,
+        from_index: RewardIndex,
+        amount: Amount,
+        version: u64,
+        max_indexes: Option<Index>,
+    ) -> Result<Option<(RewardIndex, Amount)>, DposError> {
+        // Reject withdrawals computed against a state that has changed since
+        if version != self.version {
+            return Err(DposError::StaleState {
+                current: self.version,
+            });
+        }
+
+        // The request can lower the number of processed indexes, but not raise it
+        let max_delta = match max_indexes {
+            Some(max_indexes) => cmp::min(max_indexes, self.max_index_delta),
+            None => self.max_index_delta,
+        };
+
+        let stake = self.stake_of(user.address);
+        Ok(self.withdraw_with_rewards(user, from_index, amount, stake, max_delta, false))
+    }
+
+    // Withdrawal outcome, none if the user doesn't have the amount at index
+    pub fn preview_withdraw(
+        &self,
+        user: &User,
+        from_index: RewardIndex,
+        amount: Amount,
+    ) -> Option<WithdrawPreview> {
+        // Check user support balance at index
+        let supported = self
+            .user_support
+            .get(&Hasher::hash(from_index, user.address))
+            .cloned()
+            .unwrap_or(0);
+        if amount == 0 || amount > supported {
+            return None;
+        }
+
+        // Compute the withdrawal without changing the state
+        let (end_index, reward, _) = self.accumulate_reward(
+            user.address,
+            from_index,
+            amount,
+            self.stake_of(user.address),
+            self.max_index_delta,
+        );
+        let locked = self.current_index < self.unlock_index(user.address, from_index);
+        let continuation = end_index < self.current_index || locked;
+
+        // Compounded reward stays on the balance
+        let compounded = self.compounded_part(user.address, reward, continuation);
+        let reward = reward - compounded;
+        let payout = if continuation {
+            reward
+        } else {
+            amount - withdraw_fee(amount, self.withdraw_fee_bps) + reward
+        };
+
+        Some(WithdrawPreview {
+            payout,
+            compounded,
+            end_index,
+            continuation,
+        })
+    }
+
+    // Withdraw the amount deposited at index with rewards in up to max rounds. Returns everything sent
+    pub fn withdraw_all(
+        &mut self,
+        user: &mut User,
+        from_index: RewardIndex,
+        amount: Amount,
+        max_rounds: u32,
+    ) -> Amount {
+        // Check the rounds limit before any state change, a withdrawal at the current index takes one round too
+        let rounds = cmp::max(1, self.estimate_withdraw_rounds(from_index));
+        if rounds > max_rounds {
+            panic!(
+                "Withdrawal requires {} rounds, only {} allowed",
+                rounds, max_rounds
+            );
+        }
+
+        // Withdraw from the upper bound index returned by each round until everything is withdrawn
+        let balance_before = user.balance;
+        let stake = self.stake_of(user.address);
+        let mut from_index = from_index;
+        while let Some((end_index, _)) =
+            self.withdraw_with_rewards(user, from_index, amount, stake, self.max_index_delta, false)
+        {
+            // The amount has been placed to the current index - it is locked, only rewards are withdrawn
+            if end_index == self.current_index {
+                break;
+            }
+
+            from_index = end_index;
+        }
+
+        // Return everything that has been sent to the user
+        user.balance - balance_before
+    }
+
+    // Withdraw the support at every index of the user with rewards within one work budget
+    pub fn user_withdraw_all(&mut self, user: &mut User) -> WithdrawAllOutcome {
+        let balance_before = user.balance;
+
+        // Number of indexes that can still be processed during that call
+        let mut budget = self.max_index_delta;
+        let stake = self.stake_of(user.address);
+
+        let indexes = self
+            .user_support_indexes
+            .get(&user.address)
+            .cloned()
+            .unwrap_or_default();
+        for index in indexes {
+            let delta = self.current_index.delta(index);
+            if delta > 0 && budget == 0 {
+                // Budget has been exhausted - the rest is left for the next call
+                return WithdrawAllOutcome {
+                    paid_out: user.balance - balance_before,
+                    incomplete: true,
+                };
+            }
+
+            // Withdraw everything at index, if the budget is not enough - support is moved to the upper bound index
+            let supported = self
+                .user_support
+                .get(&Hasher::hash(index, user.address))
+                .cloned()
+                .unwrap_or(0);
+            self.withdraw_with_rewards(user, index, supported, stake, budget, false);
+            budget -= cmp::min(delta, budget);
+        }
+
+        WithdrawAllOutcome {
+            paid_out: user.balance - balance_before,
+            incomplete: self.user_support_indexes.contains_key(&user.address),
+        }
+    }
+
+    // Withdraw several entries fully and send the payout at once
+    pub fn withdraw_batch(
+        &mut self,
+        user: &mut User,
+        entries: Vec<(RewardIndex, Amount)>,
+    ) -> Amount {
+        // Validate all entries before any state change: amounts requested at each index must be supported
+        let mut requested: HashMap<RewardIndex, Amount> = HashMap::new();
+        for (index, amount) in entries.iter() {
+            if *amount == 0 || *index > self.current_index {
+                panic!("Invalid batch entry at index {}", index);
+            }
+            *requested.entry(*index).or_insert(0) += amount;
+        }
+        for (index, amount) in requested.iter() {
+            let supported = self
+                .user_support
+                .get(&Hasher::hash(*index, user.address))
+                .cloned()
+                .unwrap_or(0);
+            if *amount > supported {
+                panic!("Not enough support at index {} to withdraw", index);
+            }
+        }
+
+        // Processed indexes of all entries together must fit into one request
+        let indexes: Index = entries
+            .iter()
+            .map(|(index, _)| self.current_index.delta(*index))
+            .sum();
+        if indexes > self.max_index_delta {
+            panic!(
+                "Batch processes {} indexes, only {} allowed",
+                indexes, self.max_index_delta
+            );
+        }
+
+        // Withdraw every entry fully, collecting the payout to send it to the user at once
+        let mut payout = User {
+            address: user.address,
+            balance: 0,
+        };
+        let stake = self.stake_of(user.address);
+        for (index, amount) in entries {
+            self.withdraw_with_rewards(
+                &mut payout,
+                index,
+                amount,
+                stake,
+                self.max_index_delta,
+                false,
+            );
+        }
+
+        user.balance += payout.balance;
+
+        payout.balance
+    }
+}
+
+impl Staking for Validator {
+    type User = User;
+
+    fn try_vote(&mut self, user: &mut User, amount: Amount) -> Result<(), DposError> {
+        self.check_vote_amount(user, amount)?;
+        self.check_delegator(user.address)?;
+        self.vote(user, amount);
+
+        Ok(())
+    }
+
+    // Withdraw the support at every index with rewards, refused if any of them is still locked
+    fn try_unvote(&mut self, user: &mut User) -> Result<(), DposError> {
+        let indexes = self
+            .user_support_indexes
+            .get(&user.address)
+            .cloned()
+            .ok_or(DposError::NoVote)?;
+
+        // Check all indexes before any state change
+        for index in indexes.iter() {
+            let unlocks_at = self.unlock_index(user.address, *index);
+            if self.current_index < unlocks_at {
+                return Err(DposError::StillLocked(unlocks_at.0));
+            }
+        }
+
+        // The last rewards are sent even if the user compounds rewards
+        let compounding = self.auto_compound.remove(&user.address);
+        let stake = self.stake_of(user.address);
+        for index in indexes {
+            let amount = self
+                .user_support
+                .get(&Hasher::hash(index, user.address))
+                .cloned()
+                .unwrap_or(0);
+
+            let mut from_index = index;
+            while let Some((end_index, _)) = self.withdraw_with_rewards(
+                user,
+                from_index,
+                amount,
+                stake,
+                self.max_index_delta,
+                false,
+            ) {
+                from_index = end_index;
+            }
+        }
+        if compounding {
+            self.auto_compound.insert(user.address);
+        }
+
+        Ok(())
+    }
+
+    fn try_append_reward(&mut self, reward_id: u64, reward: Amount) -> Result<(), DposError> {
+        self.check_reward(reward_id)?;
+        self.append_reward(reward_id, reward);
+
+        Ok(())
+    }
+
+    // Send the rewards of the support at every index, the support is placed to the current index
+    fn try_claim_rewards(&mut self, user: &mut User) -> Result<(), DposError> {
+        let indexes = self
+            .user_support_indexes
+            .get(&user.address)
+            .cloned()
+            .ok_or(DposError::NoVote)?;
+
+        let stake = self.stake_of(user.address);
+        for index in indexes {
+            let amount = self
+                .user_support
+                .get(&Hasher::hash(index, user.address))
+                .cloned()
+                .unwrap_or(0);
+
+            let mut from_index = index;
+            while let Some((end_index, _)) = self.withdraw_with_rewards(
+                user,
+                from_index,
+                amount,
+                stake,
+                self.max_index_delta,
+                true,
+            ) {
+                if end_index == self.current_index {
+                    break;
+                }
+
+                from_index = end_index;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(config: ValidatorConfig) -> Validator {
+        Validator::new(&config).unwrap()
+    }
+
+    fn user(address: Address, balance: Amount) -> User {
+        User { address, balance }
+    }
+
+    #[test]
+    fn withdraw_of_large_amounts_does_not_overflow() {
+        let mut validator = validator(ValidatorConfig::default());
+        let amount = 10u128.pow(20);
+        let mut alice = user(1, amount);
+
+        validator.vote(&mut alice, amount);
+        validator.append_reward(0, amount);
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), amount, version, None)
+            .unwrap();
+
+        assert_eq!(alice.balance, amount + amount * SHARE / 100);
+    }
+
+    #[test]
+    fn full_withdrawal_lowers_total_support() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        let mut bob = user(2, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.append_reward(0, 1000);
+
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(validator.support_at(validator.current_index), 100);
+        assert_eq!(alice.balance, 100 + 150);
+
+        // The rest of the delegators share the next reward without dilution
+        validator.append_reward(1, 1000);
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut bob, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(validator.support_at(validator.current_index), 0);
+        assert_eq!(bob.balance, 100 + 150 + 300);
+    }
+
+    #[test]
+    fn withdrawals_free_room_under_the_cap() {
+        let config = ValidatorConfig::builder()
+            .max_total_delegated(100)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 200);
+
+        assert_eq!(validator.try_vote(&mut alice, 100), Ok(()));
+        assert_eq!(
+            validator.try_vote(&mut alice, 1),
+            Err(DposError::CapExceeded(0))
+        );
+
+        // Full withdrawal with rewards
+        validator.append_reward(0, 10);
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(validator.try_vote(&mut alice, 100), Ok(()));
+
+        // Unvote through the shared trait
+        validator.append_reward(1, 10);
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert_eq!(validator.try_vote(&mut alice, 100), Ok(()));
+
+        // Principal-only unvote
+        validator.unvote(&mut alice, RewardIndex(2), 100);
+        assert_eq!(validator.try_vote(&mut alice, 100), Ok(()));
+    }
+
+    // Validator in the time-weighted mode with alice depositing 100 at the end of index 0 and bob 100 at its start
+    fn late_deposit_validator() -> Validator {
+        let config = ValidatorConfig::builder()
+            .reward_mode(RewardMode::TimeWeighted)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+
+        validator.vote(&mut user(1, 100), 100);
+        validator.vote(&mut user(2, 100), 100);
+        validator.append_reward(0, 1000);
+
+        validator.index_started_at.insert(RewardIndex(0), 1000);
+        validator.index_started_at.insert(RewardIndex(1), 1100);
+        validator
+            .deposited_at
+            .insert(Hasher::hash(RewardIndex(0), 1), 1100);
+        validator
+            .deposited_at
+            .insert(Hasher::hash(RewardIndex(0), 2), 1000);
+
+        validator
+    }
+
+    #[test]
+    fn transferred_support_keeps_its_deposit_time() {
+        let mut validator = late_deposit_validator();
+
+        validator.transfer_support(1, 3);
+        assert_eq!(
+            validator.deposited_at.get(&Hasher::hash(RewardIndex(0), 1)),
+            None
+        );
+        assert_eq!(
+            validator.deposited_at[&Hasher::hash(RewardIndex(0), 3)],
+            1100
+        );
+
+        let mut carol = user(3, 0);
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut carol, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(carol.balance, 100);
+    }
+
+    #[test]
+    fn merged_support_deposit_time_is_weighted_by_amount() {
+        let mut validator = late_deposit_validator();
+
+        validator.transfer_support(1, 2);
+        assert_eq!(
+            validator.deposited_at[&Hasher::hash(RewardIndex(0), 2)],
+            1050
+        );
+
+        let mut bob = user(2, 0);
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut bob, RewardIndex(0), 200, version, None)
+            .unwrap();
+        assert_eq!(bob.balance, 200 + 150);
+    }
+
+    #[test]
+    fn appended_rewards_report_recorded_indexes() {
+        let mut validator = validator(ValidatorConfig::default());
+        let rewards = (0..5).map(|reward_id| (reward_id, 100)).collect();
+
+        assert_eq!(
+            validator.append_rewards(rewards),
+            Some((RewardIndex(0), RewardIndex(4)))
+        );
+        assert_eq!(validator.current_index, RewardIndex(5));
+        assert_eq!(
+            validator
+                .reward_history(RewardIndex(0), RewardIndex(5), 10)
+                .entries
+                .len(),
+            5
+        );
+        assert_eq!(validator.reward_for_index(RewardIndex(4)), Some(100));
+    }
+
+    #[test]
+    fn bucketed_rewards_create_no_indexes() {
+        let config = ValidatorConfig::builder().min_reward(100).build().unwrap();
+        let mut validator = validator(config);
+
+        assert_eq!(validator.append_rewards(vec![(0, 40), (1, 40)]), None);
+        assert_eq!(validator.current_index, RewardIndex(0));
+        assert_eq!(validator.pending_reward(), 80);
+
+        assert_eq!(
+            validator.append_rewards(vec![(2, 40)]),
+            Some((RewardIndex(0), RewardIndex(0)))
+        );
+        assert_eq!(validator.reward_for_index(RewardIndex(0)), Some(120));
+    }
+
+    #[test]
+    fn preview_matches_the_withdrawal() {
+        let config = ValidatorConfig::builder()
+            .withdraw_fee_bps(100)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        let preview = validator
+            .preview_withdraw(&alice, RewardIndex(0), 100)
+            .unwrap();
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(preview.payout, alice.balance);
+        assert_eq!(preview.payout, 99 + 300);
+        assert!(!preview.continuation);
+    }
+
+    #[test]
+    fn preview_of_compounding_user_sends_no_reward() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(2)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.set_auto_compound(&alice, true);
+        validator.append_reward(0, 1000);
+
+        // The support is still locked, so it stays delegated and the reward is compounded
+        let preview = validator
+            .preview_withdraw(&alice, RewardIndex(0), 100)
+            .unwrap();
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert!(preview.continuation);
+        assert_eq!(preview.payout, alice.balance);
+        assert_eq!(preview.payout, 0);
+        assert_eq!(preview.compounded, 300);
+        assert_eq!(validator.stake_of(1), 400);
+
+        // Full withdrawal sends the reward
+        validator.append_reward(1, 1000);
+        let preview = validator
+            .preview_withdraw(&alice, RewardIndex(1), 400)
+            .unwrap();
+        assert!(!preview.continuation);
+        assert_eq!(preview.compounded, 0);
+        assert_eq!(preview.payout, 400 + 300);
+    }
+
+    #[test]
+    fn preview_of_missing_support_is_none() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        assert!(validator
+            .preview_withdraw(&alice, RewardIndex(0), 100)
+            .is_none());
+
+        validator.vote(&mut alice, 100);
+        assert!(validator
+            .preview_withdraw(&alice, RewardIndex(0), 101)
+            .is_none());
+        assert!(validator
+            .preview_withdraw(&alice, RewardIndex(1), 100)
+            .is_none());
+        assert!(validator
+            .preview_withdraw(&user(2, 0), RewardIndex(0), 1)
+            .is_none());
+    }
+
+    #[test]
+    fn claims_keep_the_lockup_of_the_deposit() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(2)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        // Reward-only withdrawal during the lockup moves the support to the current index
+        assert_eq!(validator.try_claim_rewards(&mut alice), Ok(()));
+        assert_eq!(alice.balance, 300);
+        assert_eq!(validator.user_support_indexes[&1], vec![RewardIndex(1)]);
+
+        // One index before the unlock index of the deposit
+        assert_eq!(
+            validator.try_unvote(&mut alice),
+            Err(DposError::StillLocked(2))
+        );
+
+        // Exactly at the unlock index of the deposit
+        validator.append_reward(1, 1000);
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert_eq!(alice.balance, 300 + 100 + 300);
+    }
+
+    #[test]
+    fn continuations_keep_the_lockup_of_the_deposit() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(3)
+            .max_index_delta(1)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.append_reward(1, 1000);
+
+        let version = validator.version;
+        let moved = validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(moved, Some((RewardIndex(1), 100)));
+
+        validator.append_reward(2, 1000);
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(1), 100, version, None)
+            .unwrap();
+        let version = validator.version;
+        assert_eq!(
+            validator
+                .try_withdraw_with_rewards(&mut alice, RewardIndex(2), 100, version, None)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn withdraw_all_loops_continuations() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_rewards((0..2500).map(|reward_id| (reward_id, 10)).collect());
+        assert_eq!(validator.estimate_withdraw_rounds(RewardIndex(0)), 3);
+
+        let paid_out = validator.withdraw_all(&mut alice, RewardIndex(0), 100, 3);
+        assert_eq!(paid_out, 100 + 2500 * 3);
+        assert_eq!(alice.balance, paid_out);
+        assert!(!validator.is_delegator(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal requires 3 rounds, only 2 allowed")]
+    fn withdraw_all_respects_the_rounds_limit() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_rewards((0..2500).map(|reward_id| (reward_id, 10)).collect());
+        validator.withdraw_all(&mut alice, RewardIndex(0), 100, 2);
+    }
+
+    #[test]
+    fn user_withdraw_all_shares_one_budget() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_rewards((0..1500).map(|reward_id| (reward_id, 10)).collect());
+
+        let first = validator.user_withdraw_all(&mut alice);
+        assert!(first.incomplete);
+        assert_eq!(first.paid_out, 1000 * 3);
+
+        let second = validator.user_withdraw_all(&mut alice);
+        assert!(!second.incomplete);
+        assert_eq!(second.paid_out, 100 + 500 * 3);
+        assert!(!validator.is_delegator(1));
+    }
+
+    #[test]
+    fn compaction_does_not_change_payouts() {
+        let alice_address = 1 << 64;
+        let bob_address = 2 << 64;
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(alice_address, 100);
+        let mut bob = user(bob_address, 300);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 300);
+        validator.append_rewards(
+            (0..5)
+                .map(|reward_id| (reward_id, 1000 + reward_id as Amount))
+                .collect(),
+        );
+        validator.user_withdraw_all(&mut bob);
+        validator.try_claim_rewards(&mut alice).unwrap();
+        validator.append_rewards((5..8).map(|reward_id| (reward_id, 700)).collect());
+
+        let mut uncompacted = validator.clone();
+        let mut alice_uncompacted = user(alice_address, alice.balance);
+        assert!(validator.compact(validator.current_index) > 0);
+        assert_eq!(validator.reward_for_index(RewardIndex(0)), None);
+
+        let outcome = validator.user_withdraw_all(&mut alice);
+        let expected = uncompacted.user_withdraw_all(&mut alice_uncompacted);
+        assert_eq!(outcome.paid_out, expected.paid_out);
+        assert_eq!(alice.balance, alice_uncompacted.balance);
+        assert_eq!(outcome.paid_out, 100 + 3 * 210);
+    }
+
+    // Next value of the xorshift sequence
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn accumulated_reward_matches_the_loop_over_indexes() {
+        let addresses = [1 << 64, 2 << 64, 3 << 64];
+
+        for seed in 1..20 {
+            let mut state = seed;
+            let mut validator = validator(ValidatorConfig::default());
+            let mut reward_id = 0;
+
+            for _ in 0..200 {
+                let value = next_random(&mut state);
+                if value.is_multiple_of(3) {
+                    let address = addresses[(value / 3 % 3) as usize];
+                    let amount = (value % 1000 + 1) as Amount;
+                    validator.vote(&mut user(address, amount), amount);
+                } else {
+                    validator.append_reward(reward_id, (value % 10_000) as Amount);
+                    reward_id += 1;
+                }
+            }
+
+            for address in addresses.iter() {
+                for index in validator.user_support_indexes[address].iter() {
+                    let amount = validator.user_support[&Hasher::hash(*index, *address)];
+                    let delta = validator.current_index.delta(*index);
+
+                    // Reward of every index computed separately
+                    let mut expected = 0;
+                    for i in index.until(validator.current_index) {
+                        let supported = validator.support_at(i);
+                        expected += validator.reward[&i] * SHARE * amount / (100 * supported);
+                    }
+
+                    let (_, reward, _) =
+                        validator.accumulate_reward(*address, *index, amount, amount, delta);
+                    assert!(expected <= reward + 1, "{} > {}", expected, reward);
+                    assert!(
+                        reward <= expected + delta as Amount,
+                        "{} < {}",
+                        expected,
+                        reward
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn previews_can_run_in_parallel_behind_a_lock() {
+        use std::sync::{Arc, RwLock};
+        use std::thread;
+
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+        validator.vote(&mut alice, 100);
+        validator.append_rewards((0..10).map(|reward_id| (reward_id, 1000)).collect());
+
+        let shared = Arc::new(RwLock::new(validator));
+        let previews: Vec<Amount> = (0..4)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    let validator = shared.read().unwrap();
+                    validator
+                        .preview_withdraw(&user(1, 0), RewardIndex(0), 100)
+                        .unwrap()
+                        .payout
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|reader| reader.join().unwrap())
+            .collect();
+
+        // Every preview matches the withdrawal itself
+        let mut validator = shared.write().unwrap();
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None)
+            .unwrap();
+        assert_eq!(previews, vec![alice.balance; 4]);
+        assert_eq!(alice.balance, 100 + 10 * 300);
+    }
+
+    #[test]
+    fn stale_withdrawal_is_rejected() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+
+        // Both requests are computed against the same state
+        let version = validator.version;
+        assert_eq!(
+            validator.try_withdraw_with_rewards(&mut alice, RewardIndex(0), 50, version, None),
+            Ok(None)
+        );
+        assert_eq!(alice.balance, 50 + 150);
+
+        let current = validator.version;
+        assert_eq!(
+            validator.try_withdraw_with_rewards(&mut alice, RewardIndex(0), 50, version, None),
+            Err(DposError::StaleState { current })
+        );
+        assert_eq!(validator.version, current);
+        assert_eq!(alice.balance, 50 + 150);
+        assert_eq!(validator.stake_of(1), 50);
+    }
+
+    #[test]
+    fn reward_history_pages_without_gaps_or_duplicates() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.append_rewards(
+            (0..2500)
+                .map(|reward_id| (reward_id, 1 + reward_id as Amount))
+                .collect(),
+        );
+
+        // The limit above the hard one is lowered to it
+        let mut pages = vec![];
+        let mut from = Some(RewardIndex(0));
+        while let Some(next) = from {
+            let page = validator.reward_history(next, validator.current_index, 5000);
+            from = page.next_from;
+            pages.push(page.entries);
+        }
+
+        let sizes: Vec<usize> = pages.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![1000, 1000, 500]);
+        let entries: Vec<(RewardIndex, Amount)> = pages.into_iter().flatten().collect();
+        let expected: Vec<(RewardIndex, Amount)> = (0..2500)
+            .map(|index| (RewardIndex(index), 1 + index as Amount))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn projection_matches_the_appended_rewards() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 100), 100);
+        validator.append_reward(0, 1000);
+
+        let future_rewards = [500, 1000, 333];
+        let projection = validator.project(&future_rewards);
+        assert_eq!(validator.current_index, RewardIndex(1));
+
+        validator.append_rewards(vec![(1, 500), (2, 1000), (3, 333)]);
+        let applied: Vec<(RewardIndex, Amount)> = RewardIndex(1)
+            .until(validator.current_index)
+            .map(|index| (index, validator.reward[&index] * SHARE / 100))
+            .collect();
+        assert_eq!(projection, applied);
+        assert_eq!(projection.len(), 3);
+    }
+
+    #[test]
+    fn projection_of_a_paused_or_deactivating_validator() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 100), 100);
+
+        validator.pause();
+        assert_eq!(
+            validator.project(&[1000]),
+            vec![(RewardIndex(0), 1000 * SHARE / 100)]
+        );
+        assert!(validator.paused);
+
+        validator.unpause();
+        validator.begin_deactivation();
+        assert_eq!(
+            validator.project(&[1000]),
+            vec![(RewardIndex(0), 1000 * SHARE / 100)]
+        );
+        assert_eq!(validator.state, ValidatorState::Deactivating);
+    }
+
+    #[test]
+    fn vote_and_reward_are_audited_in_order() {
+        let mut validator = validator(ValidatorConfig::default());
+
+        validator.vote(&mut user(1, 100), 100);
+        validator.append_reward(0, 1000);
+
+        let entries = validator.audit.since(0);
+        let logged: Vec<(u64, AuditOp, Option<Address>, Amount, Index)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.seq,
+                    entry.op,
+                    entry.address,
+                    entry.amount,
+                    entry.index,
+                )
+            })
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                (0, AuditOp::Vote, Some(1), 100, 0),
+                (1, AuditOp::Reward, None, 1000, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn bucketed_rewards_are_audited_as_appended() {
+        let config = ValidatorConfig::builder().min_reward(100).build().unwrap();
+        let mut validator = validator(config);
+
+        validator.append_rewards(vec![(0, 40), (1, 40), (2, 40)]);
+
+        // The log sums to the rewards held on the account, not to the running bucket totals
+        let amounts: Vec<Amount> = validator
+            .audit
+            .since(0)
+            .iter()
+            .map(|entry| entry.amount)
+            .collect();
+        assert_eq!(amounts, vec![40, 40, 40]);
+        assert_eq!(validator.total_balance, 120);
+        assert_eq!(validator.reward_for_index(RewardIndex(0)), Some(120));
+    }
+
+    #[test]
+    fn compounding_user_is_paid_out_on_unvote() {
+        let config = ValidatorConfig::builder()
+            .lockup_rewards(1)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.set_auto_compound(&alice, true);
+        validator.append_reward(0, 1000);
+        validator.begin_deactivation();
+
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert_eq!(alice.balance, 100 + 300);
+        assert_eq!(validator.stake_of(1), 0);
+        assert!(!validator.is_delegator(1));
+        assert!(validator.auto_compound.contains(&1));
+
+        assert_eq!(validator.finalize_deactivation(&mut user(2, 0)), 700);
+    }
+
+    #[test]
+    fn compounding_respects_the_delegation_cap() {
+        let config = ValidatorConfig::builder()
+            .max_total_delegated(250)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.set_auto_compound(&alice, true);
+
+        // Only 150 of the reward of 300 fits under the cap, the rest is sent
+        validator.append_reward(0, 1000);
+        assert_eq!(validator.try_claim_rewards(&mut alice), Ok(()));
+        assert_eq!(validator.stake_of(1), 250);
+        assert_eq!(alice.balance, 150);
+
+        // The cap is reached, the whole reward is sent
+        validator.append_reward(1, 1000);
+        assert_eq!(validator.try_claim_rewards(&mut alice), Ok(()));
+        assert_eq!(validator.stake_of(1), 250);
+        assert_eq!(validator.support_at(validator.current_index), 250);
+        assert_eq!(alice.balance, 150 + 300);
+    }
+
+    #[test]
+    fn compounding_delegator_ends_with_more_stake() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 100);
+        let mut bob = user(2 << 64, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.set_auto_compound(&alice, true);
+
+        for reward_id in 0..5 {
+            validator.append_reward(reward_id, 1000);
+            validator.try_claim_rewards(&mut alice).unwrap();
+            validator.try_claim_rewards(&mut bob).unwrap();
+        }
+
+        assert_eq!(alice.balance, 0);
+        assert!(bob.balance > 0);
+        assert_eq!(validator.stake_of(2 << 64), 100);
+        assert!(validator.stake_of(1 << 64) > 100 + bob.balance);
+    }
+
+    // Validator where alice has 50 deposited at index 0 and 50 at index 1, two rewards of 1000 appended
+    fn two_deposits_validator(config: ValidatorConfig) -> (Validator, User) {
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+
+        validator.vote(&mut alice, 50);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut alice, 50);
+        validator.append_reward(1, 1000);
+
+        (validator, alice)
+    }
+
+    #[test]
+    fn tier_is_picked_from_the_whole_stake() {
+        // Stake of 100 reaches the threshold, the user share is 45% instead of 30%
+        let config = ValidatorConfig::builder().tier(100, 150).build().unwrap();
+        let (mut validator, mut alice) = two_deposits_validator(config);
+
+        let outcome = validator.user_withdraw_all(&mut alice);
+        assert_eq!(outcome.paid_out, 100 + 675 + 225);
+        assert_eq!(alice.balance, outcome.paid_out);
+        assert_eq!(validator.total_balance, 100 + 2000 - outcome.paid_out);
+    }
+
+    #[test]
+    fn partial_withdrawal_keeps_the_tier_of_the_stake() {
+        let config = ValidatorConfig::builder().tier(100, 150).build().unwrap();
+        let (mut validator, mut alice) = two_deposits_validator(config);
+
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 50, version, None)
+            .unwrap();
+        assert_eq!(alice.balance, 50 + 675);
+        assert_eq!(validator.total_balance, 100 + 2000 - alice.balance);
+    }
+
+    #[test]
+    fn flat_rewards_without_tiers() {
+        let (mut validator, mut alice) = two_deposits_validator(ValidatorConfig::default());
+
+        let outcome = validator.user_withdraw_all(&mut alice);
+        assert_eq!(outcome.paid_out, 100 + 450 + 150);
+        assert_eq!(validator.total_balance, 100 + 2000 - outcome.paid_out);
+    }
+
+    #[test]
+    fn top_delegators_with_their_share() {
+        let mut validator = validator(ValidatorConfig::default());
+        let (alice, bob, carol, dave) = (1 << 64, 2 << 64, 3 << 64, 4 << 64);
+
+        validator.vote(&mut user(alice, 1), 1);
+        validator.vote(&mut user(bob, 2), 2);
+        validator.vote(&mut user(dave, 1), 1);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut user(carol, 2), 2);
+
+        assert_eq!(
+            validator.top_delegators(3),
+            vec![(bob, 2, 3333), (carol, 2, 3333), (alice, 1, 1666)]
+        );
+        assert_eq!(validator.top_delegators(10).len(), 4);
+        assert_eq!(validator.top_delegators(10)[3], (dave, 1, 1666));
+    }
+
+    #[test]
+    fn transferred_support_withdraws_what_the_source_could() {
+        let (alice, bob, carol) = (1 << 64, 2 << 64, 3 << 64);
+        let mut validator = validator(ValidatorConfig::default());
+
+        validator.vote(&mut user(alice, 100), 100);
+        validator.vote(&mut user(bob, 100), 100);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut user(alice, 50), 50);
+        validator.append_reward(1, 777);
+
+        let expected = validator
+            .clone()
+            .user_withdraw_all(&mut user(alice, 0))
+            .paid_out;
+
+        validator.transfer_support(alice, carol);
+        let mut carol_user = user(carol, 0);
+        assert_eq!(
+            validator.user_withdraw_all(&mut carol_user).paid_out,
+            expected
+        );
+        assert_eq!(carol_user.balance, expected);
+
+        assert_eq!(validator.stake_of(alice), 0);
+        assert!(!validator.is_delegator(alice));
+        assert_eq!(
+            validator.try_unvote(&mut user(alice, 0)),
+            Err(DposError::NoVote)
+        );
+    }
+
+    #[test]
+    fn transfers_keep_the_delegator_limit_and_compounding() {
+        let (alice, bob, carol, dave) = (1 << 64, 2 << 64, 3 << 64, 4 << 64);
+        let config = ValidatorConfig::builder()
+            .max_delegators(2)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let alice_user = user(alice, 100);
+
+        validator.vote(&mut user(alice, 100), 100);
+        validator.vote(&mut user(bob, 100), 100);
+        validator.set_auto_compound(&alice_user, true);
+
+        // Carol takes the place of alice, the limit is still reached
+        validator.transfer_support(alice, carol);
+        assert_eq!(validator.delegator_count(), 2);
+        assert!(validator.auto_compound.contains(&carol));
+        assert!(!validator.auto_compound.contains(&alice));
+        assert_eq!(
+            validator.try_vote(&mut user(dave, 100), 100),
+            Err(DposError::DelegatorLimitReached(2))
+        );
+
+        // Merged support keeps the setting of the destination
+        validator.transfer_support(carol, bob);
+        assert_eq!(validator.delegator_count(), 1);
+        assert!(validator.auto_compound.is_empty());
+        assert_eq!(validator.stake_of(bob), 200);
+    }
+
+    #[test]
+    fn unvote_pays_no_reward() {
+        let (alice_address, bob_address) = (1 << 64, 2 << 64);
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(alice_address, 100);
+        let mut bob = user(bob_address, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.append_reward(0, 1000);
+
+        validator.unvote(&mut alice, RewardIndex(0), 100);
+        assert_eq!(alice.balance, 100);
+        assert!(!validator.is_delegator(alice_address));
+        assert_eq!(validator.support_at(validator.current_index), 100);
+
+        // The forgone reward stays on the balance
+        assert_eq!(validator.total_balance, 100 + 1000);
+        validator.user_withdraw_all(&mut bob);
+        assert_eq!(bob.balance, 100 + 150);
+    }
+
+    #[test]
+    fn batch_with_a_bad_entry_changes_nothing() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let (mut validator, mut alice) = two_deposits_validator(ValidatorConfig::default());
+        let version = validator.version;
+        let total_balance = validator.total_balance;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            validator.withdraw_batch(&mut alice, vec![(RewardIndex(0), 50), (RewardIndex(1), 60)])
+        }));
+        assert!(result.is_err());
+        assert_eq!(validator.version, version);
+        assert_eq!(validator.total_balance, total_balance);
+        assert_eq!(validator.stake_of(1), 100);
+        assert_eq!(alice.balance, 0);
+    }
+
+    #[test]
+    fn batch_pays_the_sum_at_once() {
+        let (mut validator, mut alice) = two_deposits_validator(ValidatorConfig::default());
+        let expected: Amount = [(RewardIndex(0), 50), (RewardIndex(1), 50)]
+            .iter()
+            .map(|(index, amount)| {
+                validator
+                    .preview_withdraw(&alice, *index, *amount)
+                    .unwrap()
+                    .payout
+            })
+            .sum();
+
+        let payout =
+            validator.withdraw_batch(&mut alice, vec![(RewardIndex(0), 50), (RewardIndex(1), 50)]);
+        assert_eq!(payout, expected);
+        assert_eq!(payout, 100 + 450 + 150);
+        assert_eq!(alice.balance, payout);
+        assert_eq!(validator.withdrawn[&1], payout);
+        assert!(!validator.is_delegator(1));
+    }
+
+    #[test]
+    fn withdrawals_succeed_while_paused() {
+        let (alice_address, bob_address) = (1 << 64, 2 << 64);
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(alice_address, 100);
+        let mut bob = user(bob_address, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut bob, 100);
+        validator.append_reward(0, 1000);
+        validator.pause();
+
+        assert_eq!(
+            validator.try_vote(&mut user(3 << 64, 100), 100),
+            Err(DposError::Paused)
+        );
+        assert_eq!(validator.try_append_reward(1, 1000), Err(DposError::Paused));
+
+        let version = validator.version;
+        assert_eq!(
+            validator.try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, None),
+            Ok(None)
+        );
+        assert_eq!(alice.balance, 100 + 150);
+        assert_eq!(validator.try_unvote(&mut bob), Ok(()));
+        assert_eq!(bob.balance, 100 + 150);
+        assert_eq!(validator.delegator_count(), 0);
+    }
+
+    #[test]
+    fn delegator_count_drops_after_a_full_withdrawal() {
+        let (alice_address, bob_address) = (1 << 64, 2 << 64);
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(alice_address, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.vote(&mut user(bob_address, 100), 100);
+        validator.append_reward(0, 1000);
+        assert_eq!(validator.delegator_count(), 2);
+
+        // Partial withdrawal keeps the delegator
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice, RewardIndex(0), 40, version, None)
+            .unwrap();
+        assert_eq!(validator.delegator_count(), 2);
+
+        validator.user_withdraw_all(&mut alice);
+        assert_eq!(validator.delegator_count(), 1);
+        assert_eq!(validator.delegator_list(0, 10), vec![bob_address]);
+    }
+
+    #[test]
+    fn total_pending_rewards_of_two_delegators() {
+        let (alice_address, bob_address) = (1 << 64, 2 << 64);
+        let mut validator = validator(ValidatorConfig::default());
+        let mut bob = user(bob_address, 300);
+
+        validator.vote(&mut user(alice_address, 100), 100);
+        validator.vote(&mut bob, 300);
+        assert_eq!(validator.total_pending_rewards(), 0);
+
+        validator.append_reward(0, 1000);
+        assert_eq!(validator.total_pending_rewards(), 75 + 225);
+
+        // Claimed rewards are no longer pending
+        validator.try_claim_rewards(&mut bob).unwrap();
+        assert_eq!(validator.total_pending_rewards(), 75);
+
+        validator.append_reward(1, 1000);
+        assert_eq!(validator.total_pending_rewards(), 75 + 75 + 225);
+    }
+
+    #[test]
+    fn deactivation_lifecycle_conserves_tokens() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 300);
+        let mut bob = user(2 << 64, 100);
+        let mut owner = user(3 << 64, 0);
+
+        validator.vote(&mut alice, 300);
+        validator.vote(&mut bob, 100);
+        validator.append_reward(0, 1000);
+
+        // Alice leaves before the deactivation, Bob after it
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        validator.append_reward(1, 1000);
+        validator.begin_deactivation();
+
+        assert_eq!(
+            validator.try_vote(&mut user(4 << 64, 100), 100),
+            Err(DposError::NotActive)
+        );
+        assert_eq!(
+            validator.try_append_reward(2, 1000),
+            Err(DposError::NotActive)
+        );
+
+        assert_eq!(validator.try_unvote(&mut bob), Ok(()));
+        validator.finalize_deactivation(&mut owner);
+
+        // Every deposited and rewarded token ends up with a user or the owner
+        assert_eq!(validator.state, ValidatorState::Closed);
+        assert_eq!(validator.total_balance, 0);
+        assert_eq!(alice.balance, 300 + 225);
+        assert_eq!(bob.balance, 100 + 75 + 300);
+        assert_eq!(
+            alice.balance + bob.balance + owner.balance,
+            300 + 100 + 2000
+        );
+    }
+
+    #[test]
+    fn replayed_reward_is_appended_once() {
+        let mut validator = validator(ValidatorConfig::default());
+        validator.vote(&mut user(1, 100), 100);
+
+        assert_eq!(validator.try_append_reward(7, 1000), Ok(()));
+        assert_eq!(
+            validator.try_append_reward(7, 1000),
+            Err(DposError::DuplicateReward(7))
+        );
+        assert_eq!(
+            validator.try_append_reward(3, 1000),
+            Err(DposError::DuplicateReward(3))
+        );
+
+        assert_eq!(validator.current_index, RewardIndex(1));
+        assert_eq!(validator.total_balance, 100 + 1000);
+    }
+
+    #[test]
+    fn support_at_looks_up_the_last_change_point() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 100);
+        let mut bob = user(2 << 64, 50);
+
+        validator.append_reward(0, 1000);
+        validator.append_reward(1, 1000);
+        validator.vote(&mut alice, 100);
+        validator.append_reward(2, 1000);
+        validator.append_reward(3, 1000);
+        validator.vote(&mut bob, 50);
+        validator.append_reward(4, 1000);
+        validator.append_reward(5, 1000);
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+
+        assert_eq!(
+            validator.total_support,
+            vec![
+                (RewardIndex(2), 100),
+                (RewardIndex(4), 150),
+                (RewardIndex(6), 50)
+            ]
+        );
+
+        // Before the first change point
+        assert_eq!(validator.support_at(RewardIndex(0)), 0);
+        assert_eq!(validator.support_at(RewardIndex(1)), 0);
+        // At and between the change points
+        assert_eq!(validator.support_at(RewardIndex(2)), 100);
+        assert_eq!(validator.support_at(RewardIndex(3)), 100);
+        assert_eq!(validator.support_at(RewardIndex(4)), 150);
+        assert_eq!(validator.support_at(RewardIndex(5)), 150);
+        // At and after the last one
+        assert_eq!(validator.support_at(RewardIndex(6)), 50);
+        assert_eq!(validator.support_at(RewardIndex(100)), 50);
+    }
+
+    #[test]
+    fn apr_is_estimated_over_the_window() {
+        let config = ValidatorConfig::builder()
+            .rewards_per_year(10)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        assert_eq!(validator.estimated_apr_bps(10), None);
+
+        validator.vote(&mut user(1, 1000), 1000);
+        validator.append_reward(0, 100);
+        validator.append_reward(1, 200);
+
+        // Users part of the last reward is 60 of 1000 supported, ten times a year
+        assert_eq!(validator.estimated_apr_bps(1), Some(6000));
+        // Average of 30 and 60
+        assert_eq!(validator.estimated_apr_bps(2), Some(4500));
+        // The window is cut to the history
+        assert_eq!(validator.estimated_apr_bps(10), Some(4500));
+        assert_eq!(validator.estimated_apr_bps(0), None);
+    }
+
+    #[test]
+    fn user_statement_reconciles_with_the_balances() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 200);
+        let mut bob = user(2 << 64, 100);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut alice, 50);
+        validator.append_reward(1, 1000);
+        validator.vote(&mut bob, 100);
+
+        let statement = validator.user_statement(alice.address);
+        let json = serde_json::to_value(&statement).unwrap();
+        let supported: Amount = json["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["supported"].as_u64().unwrap() as Amount)
+            .sum();
+        assert_eq!(json["address"], "0x00000000000000010000000000000000");
+        assert_eq!(supported, validator.stake_of(alice.address));
+        let rewards: Amount = statement.entries.iter().map(|entry| entry.reward).sum();
+        assert_eq!(json["pending_reward"], rewards as u64);
+        assert_eq!(json["withdrawn"], 0);
+
+        // Bob has nothing pending yet, rounding takes a unit of every index
+        assert_eq!(validator.total_pending_rewards(), rewards);
+        assert_eq!(rewards, 600 - 2);
+        assert_eq!(validator.total_balance, 150 + 100 + 2000);
+
+        // Everything in the statement is sent on a full withdrawal
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        let statement = validator.user_statement(alice.address);
+        assert!(statement.entries.is_empty());
+        assert_eq!(statement.pending_reward, 0);
+        assert_eq!(statement.withdrawn, supported + rewards);
+        assert_eq!(alice.balance, 50 + statement.withdrawn);
+    }
+
+    #[test]
+    fn withdrawable_lists_every_support_index() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 150);
+
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut alice, 50);
+        validator.append_reward(1, 1000);
+
+        let entries = validator.withdrawable(alice.address);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].index, entries[0].principal),
+            (RewardIndex(0), 100)
+        );
+        assert_eq!(
+            (entries[1].index, entries[1].principal),
+            (RewardIndex(1), 50)
+        );
+
+        // Each estimate is what the withdrawal from that index pays
+        for entry in entries {
+            assert!(entry.estimated_reward > 0);
+            let mut validator = validator.clone();
+            let mut alice = user(1 << 64, 0);
+            let version = validator.version;
+            validator
+                .try_withdraw_with_rewards(&mut alice, entry.index, entry.principal, version, None)
+                .unwrap();
+            assert_eq!(alice.balance, entry.principal + entry.estimated_reward);
+        }
+        assert!(validator.withdrawable(2 << 64).is_empty());
+    }
+
+    #[test]
+    fn payout_and_fee_add_up_to_the_principal() {
+        for amount in [101, 333, 999, 1001, 12_345] {
+            let config = ValidatorConfig::builder()
+                .withdraw_fee_bps(250)
+                .build()
+                .unwrap();
+            let mut validator = validator(config);
+            let mut alice = user(1, amount);
+
+            validator.vote(&mut alice, amount);
+            assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+
+            // The fee is rounded down and stays on the balance
+            assert_eq!(validator.collected_fees, amount * 250 / 10_000);
+            assert_eq!(alice.balance + validator.collected_fees, amount);
+            assert_eq!(validator.total_balance, validator.collected_fees);
+        }
+    }
+
+    #[test]
+    fn request_override_processes_exactly_max_indexes() {
+        let config = ValidatorConfig::builder()
+            .max_index_delta(20)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1, 100);
+        validator.vote(&mut alice, 100);
+        for reward_id in 0..35 {
+            validator.append_reward(reward_id, 100);
+        }
+
+        // Ten indexes of rewards are sent and the support moves to the continuation index
+        let version = validator.version;
+        assert_eq!(
+            validator.try_withdraw_with_rewards(&mut alice, RewardIndex(0), 100, version, Some(10)),
+            Ok(Some((RewardIndex(10), 100)))
+        );
+        assert_eq!(alice.balance, 10 * 30);
+
+        // The override can't raise the configured maximum
+        let version = validator.version;
+        assert_eq!(
+            validator.try_withdraw_with_rewards(
+                &mut alice,
+                RewardIndex(10),
+                100,
+                version,
+                Some(1000)
+            ),
+            Ok(Some((RewardIndex(30), 100)))
+        );
+        assert_eq!(alice.balance, 30 * 30);
+
+        let version = validator.version;
+        assert_eq!(
+            validator.try_withdraw_with_rewards(
+                &mut alice,
+                RewardIndex(30),
+                100,
+                version,
+                Some(10)
+            ),
+            Ok(None)
+        );
+        assert_eq!(alice.balance, 100 + 35 * 30);
+    }
+
+    #[test]
+    fn delegator_limit_rejects_only_new_addresses() {
+        let config = ValidatorConfig::builder()
+            .max_delegators(3)
+            .build()
+            .unwrap();
+        let mut validator = validator(config);
+        let mut alice = user(1 << 64, 300);
+        for address in [alice.address, 2 << 64, 3 << 64] {
+            validator.vote(&mut user(address, 100), 100);
+        }
+
+        assert_eq!(
+            validator.try_vote(&mut user(4 << 64, 100), 100),
+            Err(DposError::DelegatorLimitReached(3))
+        );
+
+        // An existing delegator still adds support
+        validator.append_reward(0, 1000);
+        assert_eq!(validator.try_vote(&mut alice, 100), Ok(()));
+        assert_eq!(validator.stake_of(alice.address), 200);
+        assert_eq!(validator.delegator_count(), 3);
+
+        // A place frees up once a delegator leaves
+        assert_eq!(validator.try_unvote(&mut user(2 << 64, 0)), Ok(()));
+        assert_eq!(validator.try_vote(&mut user(4 << 64, 100), 100), Ok(()));
+    }
+
+    #[test]
+    fn stake_of_sums_every_support_index() {
+        let (alice, bob) = (1 << 64, 2 << 64);
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice_user = user(alice, 175);
+
+        validator.vote(&mut alice_user, 100);
+        validator.append_reward(0, 1000);
+        validator.vote(&mut alice_user, 50);
+        validator.append_reward(1, 1000);
+        validator.vote(&mut alice_user, 25);
+
+        assert!(validator.is_delegator(alice));
+        assert_eq!(validator.stake_of(alice), 175);
+
+        // The withdrawn index is skipped
+        let version = validator.version;
+        validator
+            .try_withdraw_with_rewards(&mut alice_user, RewardIndex(1), 50, version, None)
+            .unwrap();
+        assert!(validator.is_delegator(alice));
+        assert_eq!(validator.stake_of(alice), 125);
+
+        // Bob has never delegated
+        assert!(!validator.is_delegator(bob));
+        assert_eq!(validator.stake_of(bob), 0);
+    }
+
+    #[test]
+    fn reward_of_an_index_with_zero_support_is_not_shared() {
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 100);
+        let mut bob = user(2 << 64, 100);
+
+        // Everybody leaves, the reward at index 1 has no support to share it
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        validator.append_reward(1, 1000);
+        assert_eq!(validator.support_at(RewardIndex(1)), 0);
+
+        validator.vote(&mut bob, 100);
+        validator.append_reward(2, 1000);
+        assert_eq!(validator.try_unvote(&mut bob), Ok(()));
+        assert_eq!(alice.balance, 100 + 300);
+        assert_eq!(bob.balance, 100 + 300);
+    }
+
+    #[test]
+    fn index_with_zero_total_support_contributes_no_reward() {
+        // Support of index 1 is zero while the user still has some there, the withdrawal crosses it
+        let mut validator = validator(ValidatorConfig::default());
+        let mut alice = user(1 << 64, 100);
+        validator.vote(&mut alice, 100);
+        validator.append_reward(0, 1000);
+        validator.total_support.push((RewardIndex(1), 0));
+        validator.append_reward(1, 1000);
+        validator.total_support.push((RewardIndex(2), 100));
+        validator.append_reward(2, 1000);
+
+        assert_eq!(validator.try_unvote(&mut alice), Ok(()));
+        assert_eq!(alice.balance, 100 + 600);
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+
+use super::Amount;
+use super::Index;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DposError {
+    // Validator does not accept votes and rewards for now
+    Paused,
+    // Validator is deactivating or closed
+    NotActive,
+    // Vote amount is below the minimal vote
+    BelowMinVote(Amount),
+    // User balance doesn't cover the vote, the balance
+    InsufficientBalance(Amount),
+    // Vote doesn't fit into the delegation cap, the available amount
+    CapExceeded(Amount),
+    // Validator has the maximum number of delegators, the limit
+    DelegatorLimitReached(usize),
+    // User already has a vote
+    AlreadyVoted,
+    // User has no vote
+    NoVote,
+    // Total delegated doesn't cover the vote, the state is inconsistent
+    NoDelegation,
+    // Vote is locked until the index
+    StillLocked(Index),
+    // Reward id has already been appended
+    DuplicateReward(u64),
+    // Request has been computed against an older state version
+    StaleState { current: u64 },
+}
+
+impl fmt::Display for DposError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DposError::Paused => write!(f, "Validator is paused"),
+            DposError::NotActive => write!(f, "Validator is not active"),
+            DposError::BelowMinVote(min_vote) => {
+                write!(f, "Vote amount is below the minimum of {}", min_vote)
+            }
+            DposError::InsufficientBalance(balance) => {
+                write!(f, "Vote amount is above the user balance of {}", balance)
+            }
+            DposError::CapExceeded(available) => {
+                write!(f, "Delegation cap exceeded, {} available", available)
+            }
+            DposError::DelegatorLimitReached(max_delegators) => {
+                write!(f, "Delegator limit of {} reached", max_delegators)
+            }
+            DposError::AlreadyVoted => write!(f, "Unvote before revoting"),
+            DposError::NoVote => write!(f, "No vote"),
+            DposError::NoDelegation => write!(f, "Total delegated doesn't cover the vote"),
+            DposError::StillLocked(unlocks_at) => write!(f, "Vote is locked until {}", unlocks_at),
+            DposError::DuplicateReward(reward_id) => write!(f, "Duplicate reward {}", reward_id),
+            DposError::StaleState { current } => {
+                write!(f, "Stale state, current version is {}", current)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DposError {}
+
+// Operations shared by all validator implementations. Nothing is changed if an error is returned
+pub trait Staking {
+    type User;
+
+    // Delegate the amount
+    fn try_vote(&mut self, user: &mut Self::User, amount: Amount) -> Result<(), DposError>;
+    // Withdraw the whole stake with its rewards
+    fn try_unvote(&mut self, user: &mut Self::User) -> Result<(), DposError>;
+    fn try_append_reward(&mut self, reward_id: u64, reward: Amount) -> Result<(), DposError>;
+    // Send the rewards earned so far, the stake stays delegated
+    fn try_claim_rewards(&mut self, user: &mut Self::User) -> Result<(), DposError>;
+}
@@ -0,0 +1,92 @@
+// Helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use dpos::acc_impl;
+use dpos::config::ValidatorConfig;
+use dpos::new_impl;
+use dpos::old_impl;
+use dpos::traits::Staking;
+use dpos::Address;
+use dpos::Amount;
+
+// Addresses far enough apart for the support keys of the index-based validator not to collide
+pub const ALICE: Address = 1 << 64;
+pub const BOB: Address = 2 << 64;
+pub const CAROL: Address = 3 << 64;
+
+// Validator that can be driven by a scripted scenario through the shared trait
+pub trait Scripted: Staking + Sized {
+    fn create(config: &ValidatorConfig) -> Self;
+    fn user(address: Address, balance: Amount) -> Self::User;
+    fn balance(user: &Self::User) -> Amount;
+    // Token balance held by the validator
+    fn total_balance(&self) -> Amount;
+    // Amount delegated by all users
+    fn delegated(&self) -> Amount;
+}
+
+impl Scripted for old_impl::Validator {
+    fn create(config: &ValidatorConfig) -> Self {
+        old_impl::Validator::new(config).unwrap()
+    }
+
+    fn user(address: Address, balance: Amount) -> old_impl::User {
+        old_impl::User { address, balance }
+    }
+
+    fn balance(user: &old_impl::User) -> Amount {
+        user.balance
+    }
+
+    fn total_balance(&self) -> Amount {
+        self.total_balance
+    }
+
+    fn delegated(&self) -> Amount {
+        self.support_at(self.current_index)
+    }
+}
+
+impl Scripted for new_impl::Validator {
+    fn create(config: &ValidatorConfig) -> Self {
+        new_impl::Validator::new(config).unwrap()
+    }
+
+    fn user(address: Address, balance: Amount) -> new_impl::User {
+        new_impl::User { address, balance }
+    }
+
+    fn balance(user: &new_impl::User) -> Amount {
+        user.balance
+    }
+
+    fn total_balance(&self) -> Amount {
+        self.total_balance
+    }
+
+    fn delegated(&self) -> Amount {
+        self.total_delegated
+    }
+}
+
+impl Scripted for acc_impl::Validator {
+    fn create(config: &ValidatorConfig) -> Self {
+        acc_impl::Validator::new(config).unwrap()
+    }
+
+    fn user(address: Address, balance: Amount) -> new_impl::User {
+        new_impl::User { address, balance }
+    }
+
+    fn balance(user: &new_impl::User) -> Amount {
+        user.balance
+    }
+
+    fn total_balance(&self) -> Amount {
+        self.total_balance
+    }
+
+    fn delegated(&self) -> Amount {
+        self.total_delegated
+    }
+}
@@ -0,0 +1,255 @@
+mod common;
+
+use common::{Scripted, ALICE, BOB, CAROL};
+use dpos::acc_impl;
+use dpos::config::ValidatorConfig;
+use dpos::new_impl;
+use dpos::old_impl;
+use dpos::traits::Staking;
+use dpos::Address;
+use dpos::Amount;
+use proptest::prelude::*;
+
+const INITIAL_BALANCE: Amount = 1_000_000;
+const USERS: [Address; 3] = [ALICE, BOB, CAROL];
+
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    // Delegate the amount, adding it to the existing stake
+    Deposit(usize, Amount),
+    Reward(Amount),
+    // Send the rewards earned so far
+    Claim(usize),
+    // Withdraw the whole stake with its rewards
+    Withdraw(usize),
+}
+
+use Step::*;
+
+// Validator that runs the steps of a scenario
+trait Harness: Scripted {
+    fn deposit(&mut self, user: &mut Self::User, amount: Amount);
+}
+
+impl Harness for old_impl::Validator {
+    fn deposit(&mut self, user: &mut old_impl::User, amount: Amount) {
+        self.try_vote(user, amount).unwrap();
+    }
+}
+
+impl Harness for new_impl::Validator {
+    fn deposit(&mut self, user: &mut new_impl::User, amount: Amount) {
+        if self.is_delegator(user.address) {
+            self.add_stake(user, amount);
+        } else {
+            self.try_vote(user, amount).unwrap();
+        }
+    }
+}
+
+impl Harness for acc_impl::Validator {
+    fn deposit(&mut self, user: &mut new_impl::User, amount: Amount) {
+        if self.stakes.contains_key(&user.address) {
+            self.add_stake(user, amount);
+        } else {
+            self.try_vote(user, amount).unwrap();
+        }
+    }
+}
+
+// Final user balances after the steps and the withdrawal of everything that is left
+fn run<V: Harness>(steps: &[Step]) -> Vec<Amount> {
+    let mut validator = V::create(&ValidatorConfig::default());
+    let mut users: Vec<V::User> = USERS
+        .iter()
+        .map(|address| V::user(*address, INITIAL_BALANCE))
+        .collect();
+    let mut reward_id = 0;
+
+    for step in steps {
+        match *step {
+            Deposit(user, amount) => validator.deposit(&mut users[user], amount),
+            Reward(reward) => {
+                validator.try_append_reward(reward_id, reward).unwrap();
+                reward_id += 1;
+            }
+            // Steps for users without stake are no-ops in every implementation
+            Claim(user) => {
+                let _ = validator.try_claim_rewards(&mut users[user]);
+            }
+            Withdraw(user) => {
+                let _ = validator.try_unvote(&mut users[user]);
+            }
+        }
+    }
+
+    for user in users.iter_mut() {
+        let _ = validator.try_unvote(user);
+    }
+
+    users.iter().map(V::balance).collect()
+}
+
+// Compare the balances of all implementations, every settlement can round differently by a unit
+fn compare(name: &str, steps: &[Step]) -> Result<(), String> {
+    let results = [
+        ("old_impl", run::<old_impl::Validator>(steps)),
+        ("new_impl", run::<new_impl::Validator>(steps)),
+        ("acc_impl", run::<acc_impl::Validator>(steps)),
+    ];
+    let tolerance = steps.len() as Amount + USERS.len() as Amount;
+
+    let disagree = (0..USERS.len()).any(|user| {
+        let balances = results.iter().map(|(_, balances)| balances[user]);
+        balances.clone().max().unwrap() - balances.min().unwrap() > tolerance
+    });
+    if !disagree {
+        return Ok(());
+    }
+
+    // Table of the final balances, one row per user
+    let mut diff = format!(
+        "scenario '{}' disagrees beyond {} units\nsteps: {:?}\n{:>6} {:>12} {:>12} {:>12}\n",
+        name, tolerance, steps, "user", results[0].0, results[1].0, results[2].0
+    );
+    for user in 0..USERS.len() {
+        diff.push_str(&format!(
+            "{:>6} {:>12} {:>12} {:>12}\n",
+            user, results[0].1[user], results[1].1[user], results[2].1[user]
+        ));
+    }
+
+    Err(diff)
+}
+
+#[test]
+fn hand_written_scenarios() {
+    let scenarios: Vec<(&str, Vec<Step>)> = vec![
+        (
+            "single staker",
+            vec![Deposit(0, 1000), Reward(500), Reward(700)],
+        ),
+        (
+            "late joiner",
+            vec![
+                Deposit(0, 1000),
+                Reward(1000),
+                Deposit(1, 1000),
+                Reward(1000),
+            ],
+        ),
+        (
+            "whale plus minnow",
+            vec![
+                Deposit(0, 1_000_000),
+                Deposit(1, 1),
+                Reward(10_000),
+                Reward(333),
+            ],
+        ),
+        (
+            "full exit and re-entry",
+            vec![
+                Deposit(0, 500),
+                Deposit(1, 500),
+                Reward(1000),
+                Withdraw(0),
+                Reward(1000),
+                Deposit(0, 200),
+                Reward(1000),
+            ],
+        ),
+        (
+            "claims between rewards",
+            vec![
+                Deposit(0, 300),
+                Deposit(1, 700),
+                Reward(1000),
+                Claim(0),
+                Reward(2000),
+                Claim(1),
+                Claim(0),
+                Reward(500),
+            ],
+        ),
+        (
+            "reward before anybody stakes",
+            vec![Reward(1000), Deposit(0, 100), Reward(1000)],
+        ),
+        (
+            "three uneven stakers",
+            vec![
+                Deposit(0, 1),
+                Deposit(1, 10),
+                Deposit(2, 100),
+                Reward(1111),
+                Reward(2222),
+            ],
+        ),
+        (
+            "top-up mid-way",
+            vec![
+                Deposit(0, 100),
+                Deposit(1, 100),
+                Reward(1000),
+                Deposit(0, 200),
+                Reward(1000),
+            ],
+        ),
+        (
+            "many small rewards",
+            (0..50)
+                .map(|_| Reward(7))
+                .chain(vec![Deposit(0, 3), Deposit(1, 5)])
+                .chain((0..50).map(|_| Reward(7)))
+                .collect(),
+        ),
+        (
+            "exit before any reward",
+            vec![Deposit(0, 100), Withdraw(0), Deposit(1, 100), Reward(1000)],
+        ),
+        (
+            "zero rewards",
+            vec![Deposit(0, 100), Reward(0), Deposit(1, 100), Reward(0)],
+        ),
+        (
+            "everybody leaves and comes back",
+            vec![
+                Deposit(0, 100),
+                Deposit(1, 100),
+                Reward(600),
+                Withdraw(0),
+                Withdraw(1),
+                Reward(600),
+                Deposit(1, 300),
+                Deposit(2, 100),
+                Reward(600),
+                Claim(2),
+            ],
+        ),
+    ];
+
+    let failures: Vec<String> = scenarios
+        .iter()
+        .filter_map(|(name, steps)| compare(name, steps).err())
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        (0..3usize, 1..10_000 as Amount).prop_map(|(user, amount)| Deposit(user, amount)),
+        (0..100_000 as Amount).prop_map(Reward),
+        (0..3usize).prop_map(Claim),
+        (0..3usize).prop_map(Withdraw),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn random_scenarios(steps in prop::collection::vec(step(), 1..30)) {
+        if let Err(diff) = compare("random", &steps) {
+            prop_assert!(false, "{}", diff);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{Scripted, ALICE, BOB, CAROL};
+use dpos::acc_impl;
+use dpos::config::ValidatorConfig;
+use dpos::new_impl;
+use dpos::old_impl;
+use dpos::traits::DposError;
+use dpos::Amount;
+use dpos::SHARE;
+use proptest::prelude::*;
+
+const INITIAL_BALANCE: Amount = 1000;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Vote(usize, Amount),
+    Unvote(usize),
+    Reward(Amount),
+    Claim(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..3usize, 0..1500 as Amount).prop_map(|(user, amount)| Op::Vote(user, amount)),
+        (0..3usize).prop_map(Op::Unvote),
+        (0..5000 as Amount).prop_map(Op::Reward),
+        (0..3usize).prop_map(Op::Claim),
+    ]
+}
+
+// Run the operations checking the invariants after every step. Rejected operations must not change anything
+fn check<V: Scripted>(ops: &[Op]) -> Result<(), TestCaseError> {
+    let mut validator = V::create(&ValidatorConfig::default());
+    let mut users: Vec<V::User> = [ALICE, BOB, CAROL]
+        .iter()
+        .map(|address| V::user(*address, INITIAL_BALANCE))
+        .collect();
+    let mut rewards = 0;
+    let mut reward_id = 0;
+
+    for op in ops {
+        let balances: Vec<Amount> = users.iter().map(V::balance).collect();
+        let total_balance = validator.total_balance();
+
+        let result = match op {
+            Op::Vote(user, amount) => validator.try_vote(&mut users[*user], *amount),
+            Op::Unvote(user) => validator.try_unvote(&mut users[*user]),
+            Op::Reward(reward) => {
+                reward_id += 1;
+                let result = validator.try_append_reward(reward_id, *reward);
+                if result.is_ok() {
+                    rewards += reward;
+                }
+                result
+            }
+            Op::Claim(user) => validator.try_claim_rewards(&mut users[*user]),
+        };
+
+        if result.is_err() {
+            let unchanged: Vec<Amount> = users.iter().map(V::balance).collect();
+            prop_assert_eq!(&unchanged, &balances, "{:?} rejected with changes", op);
+            prop_assert_eq!(validator.total_balance(), total_balance);
+        }
+
+        // Closed system: tokens only come in with rewards
+        let user_balances: Amount = users.iter().map(V::balance).sum();
+        prop_assert_eq!(
+            user_balances + validator.total_balance(),
+            3 * INITIAL_BALANCE + rewards,
+            "tokens are created or lost after {:?}",
+            op
+        );
+
+        // Users never get more than their deposits back and users part of the rewards
+        prop_assert!(user_balances <= 3 * INITIAL_BALANCE + rewards * SHARE / 100);
+
+        // Delegated amount is held by the validator
+        prop_assert!(validator.delegated() <= validator.total_balance());
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn index_based_invariants(ops in prop::collection::vec(op(), 1..40)) {
+        check::<old_impl::Validator>(&ops)?;
+    }
+
+    #[test]
+    fn vote_based_invariants(ops in prop::collection::vec(op(), 1..40)) {
+        check::<new_impl::Validator>(&ops)?;
+    }
+
+    #[test]
+    fn accumulator_invariants(ops in prop::collection::vec(op(), 1..40)) {
+        check::<acc_impl::Validator>(&ops)?;
+    }
+}
+
+// Regression: votes above the user balance used to underflow it
+#[test]
+fn votes_above_the_balance_are_rejected() {
+    fn vote_above_balance<V: Scripted>() {
+        let mut validator = V::create(&ValidatorConfig::default());
+        let mut alice = V::user(ALICE, 100);
+
+        assert_eq!(
+            validator.try_vote(&mut alice, 101),
+            Err(DposError::InsufficientBalance(100))
+        );
+        assert_eq!(V::balance(&alice), 100);
+    }
+
+    vote_above_balance::<old_impl::Validator>();
+    vote_above_balance::<new_impl::Validator>();
+    vote_above_balance::<acc_impl::Validator>();
+}
+
+// Regression: the accumulator validator rounded the settled reward down and paid a revoting user a unit too much
+#[test]
+fn revoting_does_not_overpay() {
+    let ops = [
+        Op::Vote(1, 10),
+        Op::Reward(3527),
+        Op::Unvote(1),
+        Op::Vote(1, 6),
+        Op::Reward(894),
+        Op::Unvote(1),
+    ];
+
+    check::<old_impl::Validator>(&ops).unwrap();
+    check::<new_impl::Validator>(&ops).unwrap();
+    check::<acc_impl::Validator>(&ops).unwrap();
+}
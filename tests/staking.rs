@@ -0,0 +1,77 @@
+mod common;
+
+use common::{Scripted, ALICE, BOB};
+use dpos::acc_impl;
+use dpos::config::ValidatorConfig;
+use dpos::new_impl;
+use dpos::old_impl;
+use dpos::traits::DposError;
+use dpos::Amount;
+
+// Two delegators, a claim in the middle, one of them leaves before the last reward. Returns the final balances
+fn scenario<V: Scripted>() -> (Amount, Amount) {
+    let mut validator = V::create(&ValidatorConfig::default());
+    let mut alice = V::user(ALICE, 1000);
+    let mut bob = V::user(BOB, 1000);
+
+    validator.try_vote(&mut alice, 100).unwrap();
+    validator.try_vote(&mut bob, 300).unwrap();
+    validator.try_append_reward(0, 1000).unwrap();
+
+    validator.try_claim_rewards(&mut alice).unwrap();
+    validator.try_append_reward(1, 2000).unwrap();
+
+    validator.try_unvote(&mut bob).unwrap();
+    validator.try_append_reward(2, 500).unwrap();
+
+    validator.try_unvote(&mut alice).unwrap();
+    assert_eq!(validator.try_unvote(&mut alice), Err(DposError::NoVote));
+
+    (V::balance(&alice), V::balance(&bob))
+}
+
+#[test]
+fn implementations_pay_the_same_through_the_trait() {
+    let expected = (1000 + 75 + 150 + 150, 1000 + 225 + 450);
+
+    assert_eq!(scenario::<old_impl::Validator>(), expected);
+    assert_eq!(scenario::<new_impl::Validator>(), expected);
+    assert_eq!(scenario::<acc_impl::Validator>(), expected);
+}
+
+// Votes exactly at the minimum are accepted, one below is rejected
+fn min_vote<V: Scripted>() {
+    let config = ValidatorConfig::builder().min_vote(10).build().unwrap();
+    let mut validator = V::create(&config);
+    let mut alice = V::user(ALICE, 100);
+    let mut bob = V::user(BOB, 100);
+
+    assert_eq!(
+        validator.try_vote(&mut alice, 9),
+        Err(DposError::BelowMinVote(10))
+    );
+    assert_eq!(V::balance(&alice), 100);
+    assert_eq!(validator.try_vote(&mut bob, 10), Ok(()));
+    assert_eq!(V::balance(&bob), 90);
+}
+
+#[test]
+fn votes_below_the_minimum_are_rejected() {
+    min_vote::<old_impl::Validator>();
+    min_vote::<new_impl::Validator>();
+    min_vote::<acc_impl::Validator>();
+}
+
+#[test]
+fn accumulator_validator_rejects_unsupported_parameters() {
+    let config = ValidatorConfig::builder()
+        .withdraw_fee_bps(100)
+        .lockup_rewards(2)
+        .build()
+        .unwrap();
+
+    let error = acc_impl::Validator::new(&config).err().unwrap();
+    assert_eq!(error.violations.len(), 2);
+    assert!(old_impl::Validator::new(&config).is_ok());
+    assert!(new_impl::Validator::new(&config).is_ok());
+}